@@ -1,25 +1,37 @@
 #[macro_use]
 extern crate log;
-#[cfg(feature = "router")]
+#[cfg(any(feature = "router", feature = "https"))]
 #[macro_use]
 extern crate serde_derive;
 
+pub mod listener;
 pub mod middlewares;
 pub mod proxy;
+pub mod proxy_protocol;
+mod server;
+#[cfg(any(feature = "tls", feature = "https"))]
+pub mod tls;
 
-use hyper::server::conn::AddrStream;
-use hyper::service::make_service_fn;
-use hyper::Server;
+use futures::future;
+use hyper::header::HeaderName;
 use std::fmt;
-use std::{
-    convert::Infallible,
-    sync::{Arc, Mutex},
-};
-
-use crate::proxy::middleware::Middleware;
-use crate::proxy::service::ProxyService;
-
-type Middlewares = Arc<Mutex<Vec<Box<dyn Middleware + Send + Sync>>>>;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::listener::Listener;
+use crate::proxy::circuit_breaker::{CircuitBreakerConfig, CircuitBreakers};
+use crate::proxy::client::{Clients, PoolConfig, UpstreamTimeouts};
+use crate::proxy::middleware::AsyncMiddleware;
+use crate::proxy::retry::{RetryBudget, RetryBudgetConfig};
+use crate::proxy::service::REQUEST_ID;
+use crate::server::{BoundListener, Handler};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+
+type Middlewares = Arc<Vec<Box<dyn AsyncMiddleware + Send + Sync>>>;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Environment {
@@ -54,43 +66,171 @@ impl std::str::FromStr for Environment {
 }
 
 pub struct SimpleProxy {
-    port: u16,
+    listeners: Vec<Listener>,
     environment: Environment,
     middlewares: Middlewares,
+    pool: PoolConfig,
+    clients: Arc<Clients>,
+    circuit_breakers: Option<Arc<CircuitBreakers>>,
+    retry_budget: Arc<RetryBudget>,
+    upstream_timeouts: UpstreamTimeouts,
+    request_id_header: HeaderName,
+    shutdown_timeout: Duration,
 }
 
 impl SimpleProxy {
+    /// Listens on every interface (`0.0.0.0`) on `port`
     pub fn new(port: u16, environment: Environment) -> Self {
+        SimpleProxy::bind(SocketAddr::from(([0, 0, 0, 0], port)), environment)
+    }
+
+    /// Listens on `addr` only, e.g. `127.0.0.1:8080` for a sidecar or `[::]:8080` for IPv6
+    pub fn bind(addr: SocketAddr, environment: Environment) -> Self {
         SimpleProxy {
-            port,
+            listeners: vec![Listener::tcp(addr)],
             environment,
-            middlewares: Arc::new(Mutex::new(vec![])),
+            middlewares: Arc::new(vec![]),
+            pool: PoolConfig::default(),
+            clients: Arc::new(Clients::new()),
+            circuit_breakers: None,
+            retry_budget: Arc::new(RetryBudget::default()),
+            upstream_timeouts: UpstreamTimeouts::default(),
+            request_id_header: REQUEST_ID,
+            shutdown_timeout: Duration::from_secs(30),
         }
     }
 
+    /// Accepts connections on another socket as well
+    pub fn add_listener(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    /// Runs the proxy forever, see `run_until` to stop it
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let addr = ([0, 0, 0, 0], self.port).into();
+        self.run_until(future::pending()).await
+    }
 
-        info!("Running proxy in {} mode on: {}", self.environment, &addr);
+    /// Runs the proxy until `shutdown` resolves.
+    ///
+    /// New connections are then refused, idle keep-alive connections are closed and in-flight
+    /// requests can complete until the shutdown timeout, connections still open after it are dropped.
+    pub async fn run_until<F>(
+        &self,
+        shutdown: F,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+    where
+        F: Future<Output = ()>,
+    {
+        let (stop, stopped) = watch::channel(false);
+
+        let mut serving = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            let bound = BoundListener::bind(&listener.addr).await?;
+            info!(
+                "Running proxy in {} mode on: {}",
+                self.environment, &listener.addr
+            );
+
+            let handler = Handler {
+                middlewares: listener
+                    .middlewares
+                    .clone()
+                    .unwrap_or_else(|| Arc::clone(&self.middlewares)),
+                clients: Arc::clone(&self.clients),
+                circuit_breakers: self.circuit_breakers.clone(),
+                retry_budget: Arc::clone(&self.retry_budget),
+                timeouts: self.upstream_timeouts,
+                request_id_header: self.request_id_header.clone(),
+                proxy_protocol: listener.proxy_protocol,
+                #[cfg(feature = "tls")]
+                acceptor: listener.tls.as_ref().map(TlsConfig::acceptor),
+            };
+            serving.push(server::serve_listener(
+                bound,
+                handler,
+                stopped.clone(),
+                self.shutdown_timeout,
+            ));
+        }
 
-        let middlewares = Arc::clone(&self.middlewares);
-        let make_svc = make_service_fn(move |socket: &AddrStream| {
-            let remote_addr = socket.remote_addr();
-            let middlewares = middlewares.clone();
-            debug!("Handling connection for IP: {}", &remote_addr);
+        let stop = async move {
+            shutdown.await;
+            info!("Shutting down, no longer accepting connections");
+            let _ = stop.send(true);
+        };
+        future::join(stop, future::join_all(serving)).await;
+        info!("Proxy stopped");
 
-            async move { Ok::<_, Infallible>(ProxyService::new(middlewares, remote_addr)) }
-        });
+        Ok(())
+    }
 
-        let server = Server::bind(&addr).serve(make_svc);
+    /// Maximum time given to in-flight requests once shutdown started, 30 seconds by default
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
 
-        if let Err(e) = server.await {
-            eprintln!("server error: {}", e);
-        }
-        Ok(())
+    /// Upstream connections are pooled and shared by every connection of every listener
+    pub fn set_pool_config(&mut self, pool: PoolConfig) {
+        self.pool = pool;
+        self.rebuild_clients();
+    }
+
+    /// Clients reaching upstreams, e.g. for `Router::set_clients`.
+    /// `set_pool_config` and `set_upstream_timeouts` replace them, call them first.
+    pub fn clients(&self) -> Arc<Clients> {
+        Arc::clone(&self.clients)
+    }
+
+    /// Answers 503 without reaching an upstream host after consecutive 5xx responses
+    /// or connection errors, until a trial request succeeds once the cool-down elapsed
+    pub fn set_circuit_breaker(&mut self, config: CircuitBreakerConfig) {
+        self.circuit_breakers = Some(Arc::new(CircuitBreakers::new(config)));
+    }
+
+    /// Limits the retries of every listener together, 20% of the requests by default
+    pub fn set_retry_budget(&mut self, config: RetryBudgetConfig) {
+        self.retry_budget = Arc::new(RetryBudget::new(config));
+    }
+
+    /// Timeouts of every upstream request, routes can override them.
+    /// Elapsed timeouts are answered 504, there is none by default.
+    pub fn set_upstream_timeouts(&mut self, timeouts: UpstreamTimeouts) {
+        self.upstream_timeouts = timeouts;
+        self.rebuild_clients();
+    }
+
+    fn rebuild_clients(&mut self) {
+        self.clients = Arc::new(Clients::with_timeouts(
+            self.pool.clone(),
+            &self.upstream_timeouts,
+        ));
+    }
+
+    /// Reads request IDs from `header` instead of `X-Request-Id`, and sends them upstream and back to clients in it
+    pub fn set_request_id_header(&mut self, header: HeaderName) {
+        self.request_id_header = header;
+    }
+
+    /// Serves HTTPS instead of plain HTTP on the address given to `new` or `bind`,
+    /// other listeners have their own TLS settings
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.listeners[0].set_tls(tls);
+    }
+
+    /// Reads a PROXY protocol header on the address given to `new` or `bind`,
+    /// see `Listener::set_proxy_protocol`
+    pub fn set_proxy_protocol(&mut self, enabled: bool) {
+        self.listeners[0].set_proxy_protocol(enabled);
     }
 
-    pub fn add_middleware(&mut self, middleware: Box<dyn Middleware + Send + Sync>) {
-        self.middlewares.lock().unwrap().push(middleware)
+    /// Accepts both `Middleware` and `AsyncMiddleware` implementations,
+    /// hooks are run in the order the middlewares were added.
+    ///
+    /// Used by every listener without middlewares of its own.
+    pub fn add_middleware(&mut self, middleware: Box<dyn AsyncMiddleware + Send + Sync>) {
+        Arc::get_mut(&mut self.middlewares)
+            .expect("Cannot add a middleware while the proxy is running")
+            .push(middleware)
     }
 }
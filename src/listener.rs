@@ -0,0 +1,86 @@
+use std::fmt;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::proxy::middleware::AsyncMiddleware;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::Middlewares;
+
+#[derive(Debug, Clone)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Peers of Unix domain sockets are reported as `127.0.0.1:0` in the `ServiceContext`
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A socket the proxy accepts connections on.
+///
+/// Connections go through the proxy middlewares unless the listener has its own.
+pub struct Listener {
+    pub(crate) addr: ListenAddr,
+    pub(crate) middlewares: Option<Middlewares>,
+    pub(crate) proxy_protocol: bool,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
+}
+
+impl Listener {
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Listener::new(ListenAddr::Tcp(addr))
+    }
+
+    /// An existing socket file at `path` is replaced
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P) -> Self {
+        Listener::new(ListenAddr::Unix(path.into()))
+    }
+
+    fn new(addr: ListenAddr) -> Self {
+        Listener {
+            addr,
+            middlewares: None,
+            proxy_protocol: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    pub fn addr(&self) -> &ListenAddr {
+        &self.addr
+    }
+
+    /// Gives this listener its own middlewares instead of the proxy ones
+    pub fn add_middleware(&mut self, middleware: Box<dyn AsyncMiddleware + Send + Sync>) {
+        let middlewares = self.middlewares.get_or_insert_with(|| Arc::new(vec![]));
+        Arc::get_mut(middlewares)
+            .expect("Cannot add a middleware while the proxy is running")
+            .push(middleware)
+    }
+
+    /// Expects a PROXY protocol v1 or v2 header at the start of every connection,
+    /// e.g. behind a TCP load balancer, its client address becomes `ServiceContext::remote_addr`.
+    /// Connections without a valid header are closed.
+    pub fn set_proxy_protocol(&mut self, enabled: bool) {
+        self.proxy_protocol = enabled;
+    }
+
+    /// Serves HTTPS instead of plain HTTP
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.tls = Some(tls);
+    }
+}
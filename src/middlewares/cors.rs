@@ -48,10 +48,10 @@ impl Middleware for Cors {
     }
 
     fn before_request(
-        &mut self,
+        &self,
         req: &mut Request<Body>,
         _context: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         if req.method() == Method::OPTIONS {
             let mut response: Response<Body> = Response::new(Body::from(""));
@@ -63,10 +63,10 @@ impl Middleware for Cors {
     }
 
     fn after_request(
-        &mut self,
+        &self,
         response: Option<&mut Response<Body>>,
         _context: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         if let Some(res) = response {
             self.set_cors_headers(res);
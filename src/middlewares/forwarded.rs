@@ -0,0 +1,148 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, FORWARDED, HOST, VIA};
+use hyper::http::uri::Authority;
+use hyper::{Body, Request, Response, Version};
+use std::net::IpAddr;
+
+use crate::proxy::cidr::Cidr;
+use crate::proxy::error::MiddlewareError;
+use crate::proxy::middleware::MiddlewareResult::Next;
+use crate::proxy::middleware::{Middleware, MiddlewareResult};
+use crate::proxy::service::{ServiceContext, State};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_PORT: HeaderName = HeaderName::from_static("x-forwarded-port");
+
+#[derive(Debug, Clone, Default)]
+pub struct ForwardedConfig {
+    /// Clients whose forwarding headers are kept and appended to,
+    /// the ones sent by other clients are replaced
+    pub trusted_proxies: Vec<Cidr>,
+    /// Also emits the RFC 7239 `Forwarded` header
+    pub forwarded: bool,
+    /// Name of the proxy in the `Via` header of requests and responses, no `Via` without it
+    pub via: Option<String>,
+}
+
+/// Tells upstreams who the client is: appends its IP to `X-Forwarded-For`,
+/// sets `X-Forwarded-Proto` and `X-Forwarded-Port`, and optionally `Forwarded` and `Via`.
+///
+/// Add it before the Router for `Forwarded` to get the host the client asked for.
+pub struct Forwarded {
+    config: ForwardedConfig,
+}
+
+impl Forwarded {
+    pub fn new(config: ForwardedConfig) -> Self {
+        Forwarded { config }
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.config
+            .trusted_proxies
+            .iter()
+            .any(|range| range.contains(ip))
+    }
+
+    fn via(&self, version: Version) -> Option<String> {
+        let protocol = match version {
+            Version::HTTP_09 => "0.9",
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_2 => "2",
+            Version::HTTP_3 => "3",
+            _ => "1.1",
+        };
+        self.config
+            .via
+            .as_ref()
+            .map(|name| format!("{} {}", protocol, name))
+    }
+}
+
+/// Joins the values of `name` and `value` in a single comma-separated header
+fn append(headers: &mut HeaderMap, name: HeaderName, value: &str) -> Result<(), MiddlewareError> {
+    let mut values: Vec<&str> = headers
+        .get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    values.push(value);
+    let joined = HeaderValue::from_str(&values.join(", "))?;
+    headers.insert(name, joined);
+    Ok(())
+}
+
+/// `for` parameter of a `Forwarded` element, IPv6 addresses are quoted and bracketed
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+impl Middleware for Forwarded {
+    fn name() -> String {
+        String::from("Forwarded")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        context: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        let ip = context.remote_addr.ip().to_canonical();
+        let proto = if context.tls { "https" } else { "http" };
+        // Anything else than an authority could close the quoted `host` and forge elements
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| host.parse::<Authority>().ok());
+        let via = self.via(req.version());
+        let headers = req.headers_mut();
+
+        if !self.is_trusted(ip) {
+            for name in [X_FORWARDED_FOR, X_FORWARDED_PROTO, X_FORWARDED_PORT] {
+                headers.remove(name);
+            }
+            headers.remove(FORWARDED);
+        }
+
+        append(headers, X_FORWARDED_FOR, &ip.to_string())?;
+        if !headers.contains_key(X_FORWARDED_PROTO) {
+            headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+        }
+        if let Some(local_addr) = context.local_addr {
+            if !headers.contains_key(X_FORWARDED_PORT) {
+                headers.insert(X_FORWARDED_PORT, HeaderValue::from(local_addr.port()));
+            }
+        }
+
+        if self.config.forwarded {
+            let mut element = format!("for={};proto={}", forwarded_node(ip), proto);
+            if let Some(host) = host {
+                element.push_str(&format!(";host=\"{}\"", host));
+            }
+            append(headers, FORWARDED, &element)?;
+        }
+        if let Some(via) = via {
+            append(headers, VIA, &via)?;
+        }
+        Ok(Next)
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        _context: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if let Some(res) = res {
+            if let Some(via) = self.via(res.version()) {
+                append(res.headers_mut(), VIA, &via)?;
+            }
+        }
+        Ok(Next)
+    }
+}
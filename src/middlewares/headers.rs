@@ -0,0 +1,295 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, TE, UPGRADE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+
+#[cfg(feature = "router")]
+use crate::middlewares::router::MatchedRoute;
+use crate::proxy::error::MiddlewareError;
+use crate::proxy::middleware::MiddlewareResult::Next;
+use crate::proxy::middleware::{Middleware, MiddlewareResult};
+use crate::proxy::service::{is_upgrade_request, ServiceContext, State};
+
+/// Headers only meaningful for a single connection (RFC 7230 section 6.1), besides the ones
+/// listed in `Connection`. `Proxy-Connection` is not standard but still sent by some clients.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Changes made to the headers of a message, in this order: remove, rename, set and add.
+///
+/// `set` and `add` values are templates, `{client_ip}`, `{request_id}`, `{method}`
+/// and `{route}` (the matched route URI) are replaced by their value.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "router", derive(Deserialize), serde(default))]
+pub struct HeaderRules {
+    pub remove: Vec<String>,
+    /// Old name to new name, the values are kept
+    pub rename: HashMap<String, String>,
+    /// Replaces the values the header had
+    pub set: HashMap<String, String>,
+    /// Added along the values the header had
+    pub add: HashMap<String, String>,
+}
+
+/// A header name or value of a `HeadersConfig` that can never be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadersError {
+    InvalidName { name: String },
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for HeadersError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadersError::InvalidName { name } => write!(f, "invalid header name {:?}", name),
+            HeadersError::InvalidValue { name, value } => {
+                write!(f, "invalid value {:?} of header {}", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeadersError {}
+
+/// Header changes of requests and responses.
+///
+/// Insert it in the request `State` to apply it after the `Headers` middleware ones,
+/// the Router inserts the `headers` of the matched route.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "router", derive(Deserialize), serde(default))]
+pub struct HeadersConfig {
+    pub request: HeaderRules,
+    pub response: HeaderRules,
+}
+
+/// Applies `HeadersConfig` rules to requests and responses, and strips hop-by-hop headers.
+///
+/// Add it after the Router for the route rules to apply to requests.
+pub struct Headers {
+    config: HeadersConfig,
+    strip_hop_by_hop: bool,
+}
+
+impl HeadersConfig {
+    /// Checks every header name and value, templates are checked before being rendered
+    pub fn validate(&self) -> Result<(), HeadersError> {
+        self.request.validate()?;
+        self.response.validate()
+    }
+}
+
+impl Headers {
+    /// Fails when `config` has a header name or value that can never be sent
+    pub fn new(config: HeadersConfig) -> Result<Self, HeadersError> {
+        config.validate()?;
+        Ok(Headers {
+            config,
+            strip_hop_by_hop: true,
+        })
+    }
+
+    /// Hop-by-hop headers are stripped by default, `Connection` and `Upgrade`
+    /// are kept on protocol upgrades
+    pub fn set_strip_hop_by_hop(&mut self, strip: bool) {
+        self.strip_hop_by_hop = strip;
+    }
+}
+
+/// Method of the request, for the response templates
+struct RequestMethod(Method);
+
+/// Values the templates are rendered with
+struct Template<'a> {
+    context: &'a ServiceContext,
+    method: String,
+    route: Option<String>,
+}
+
+impl<'a> Template<'a> {
+    fn new(context: &'a ServiceContext, state: &State, method: String) -> Self {
+        #[cfg(feature = "router")]
+        let route = state.get::<MatchedRoute>().map(|route| route.uri.clone());
+        #[cfg(not(feature = "router"))]
+        let route = {
+            let _ = state;
+            None
+        };
+        Template {
+            context,
+            method,
+            route,
+        }
+    }
+
+    fn render(&self, template: &str) -> String {
+        if !template.contains('{') {
+            return template.to_string();
+        }
+        template
+            .replace("{client_ip}", &self.context.remote_addr.ip().to_string())
+            .replace("{request_id}", &self.context.req_id)
+            .replace("{method}", &self.method)
+            .replace("{route}", self.route.as_deref().unwrap_or(""))
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName, MiddlewareError> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|err| {
+        MiddlewareError::new(
+            format!("Invalid header name {}: {}", name, err),
+            None,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    })
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, MiddlewareError> {
+    HeaderValue::from_str(value).map_err(|err| {
+        MiddlewareError::new(
+            format!("Invalid header value {:?}: {}", value, err),
+            None,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    })
+}
+
+impl HeaderRules {
+    fn validate(&self) -> Result<(), HeadersError> {
+        let names = self
+            .remove
+            .iter()
+            .chain(self.rename.keys())
+            .chain(self.rename.values())
+            .chain(self.set.keys())
+            .chain(self.add.keys());
+        for name in names {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(HeadersError::InvalidName { name: name.clone() });
+            }
+        }
+        for (name, value) in self.set.iter().chain(self.add.iter()) {
+            if HeaderValue::from_str(value).is_err() {
+                return Err(HeadersError::InvalidValue {
+                    name: name.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, template: &Template) -> Result<(), MiddlewareError> {
+        for name in &self.remove {
+            headers.remove(header_name(name)?);
+        }
+        for (from, to) in &self.rename {
+            let from = header_name(from)?;
+            let to = header_name(to)?;
+            let values: Vec<HeaderValue> = headers.get_all(&from).iter().cloned().collect();
+            if values.is_empty() {
+                continue;
+            }
+            headers.remove(&from);
+            headers.remove(&to);
+            for value in values {
+                headers.append(&to, value);
+            }
+        }
+        for (name, value) in &self.set {
+            headers.insert(header_name(name)?, header_value(&template.render(value))?);
+        }
+        for (name, value) in &self.add {
+            headers.append(header_name(name)?, header_value(&template.render(value))?);
+        }
+        Ok(())
+    }
+}
+
+/// Removes hop-by-hop headers, `TE: trailers` is kept as HTTP/2 allows it (e.g. for gRPC)
+fn strip_hop_by_hop(headers: &mut HeaderMap, upgrade: bool) {
+    let listed: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect();
+    let trailers_only = headers
+        .get_all(TE)
+        .iter()
+        .all(|value| value.as_bytes().eq_ignore_ascii_case(b"trailers"));
+
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(listed.iter().map(String::as_str))
+    {
+        let kept =
+            (upgrade && (name == CONNECTION || name == UPGRADE)) || (name == TE && trailers_only);
+        if !kept {
+            headers.remove(name);
+        }
+    }
+}
+
+impl Middleware for Headers {
+    fn name() -> String {
+        String::from("Headers")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        context: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if self.strip_hop_by_hop {
+            let upgrade = is_upgrade_request(req);
+            strip_hop_by_hop(req.headers_mut(), upgrade);
+        }
+
+        state.insert(RequestMethod(req.method().clone()));
+        let template = Template::new(context, state, req.method().to_string());
+        self.config.request.apply(req.headers_mut(), &template)?;
+        if let Some(route) = state.get::<HeadersConfig>() {
+            route.request.apply(req.headers_mut(), &template)?;
+        }
+        Ok(Next)
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        context: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        let res = match res {
+            Some(res) => res,
+            None => return Ok(Next),
+        };
+        if self.strip_hop_by_hop {
+            let upgrade = res.status() == StatusCode::SWITCHING_PROTOCOLS;
+            strip_hop_by_hop(res.headers_mut(), upgrade);
+        }
+
+        let method = state
+            .get::<RequestMethod>()
+            .map_or_else(String::new, |method| method.0.to_string());
+        let template = Template::new(context, state, method);
+        self.config.response.apply(res.headers_mut(), &template)?;
+        if let Some(route) = state.get::<HeadersConfig>() {
+            route.response.apply(res.headers_mut(), &template)?;
+        }
+        Ok(Next)
+    }
+}
@@ -22,10 +22,10 @@ impl Middleware for Health {
     }
 
     fn before_request(
-        &mut self,
+        &self,
         req: &mut Request<Body>,
         _context: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         if req.uri().path() == self.route {
             let ok: Response<Body> = Response::new(Body::from(self.raw_body));
@@ -1,5 +1,7 @@
 #[cfg(feature = "cors")]
 pub mod cors;
+pub mod forwarded;
+pub mod headers;
 #[cfg(feature = "health")]
 pub mod health;
 pub mod logger;
@@ -8,6 +10,8 @@ pub mod router;
 
 #[cfg(feature = "cors")]
 pub use self::cors::Cors;
+pub use self::forwarded::Forwarded;
+pub use self::headers::Headers;
 #[cfg(feature = "health")]
 pub use self::health::Health;
 pub use self::logger::Logger;
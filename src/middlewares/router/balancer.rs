@@ -0,0 +1,388 @@
+use hyper::{Body, Request};
+use rand::seq::SliceRandom;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::health_check::{self, HealthCheckConfig, SharedClients};
+use crate::proxy::service::{ServiceContext, State};
+
+/// How a backend is picked among the backends of an upstream
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Each backend in turn
+    #[default]
+    RoundRobin,
+    /// Each backend in turn, `weight` times
+    Weighted,
+    /// Backend with the fewest requests in flight
+    LeastConnections,
+    /// Backend with the fewest requests in flight among two picked at random
+    RandomTwoChoices,
+    /// Same backend for the same `header` value, or for the same client IP without `header`
+    ConsistentHash { header: Option<String> },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackendConfig {
+    /// Host and port, may start with `https://`
+    pub host: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamConfig {
+    #[serde(default)]
+    pub strategy: Strategy,
+    pub backends: Vec<BackendConfig>,
+    /// Probes backends in the background, unhealthy backends are skipped
+    pub health_check: Option<HealthCheckConfig>,
+}
+
+#[derive(Debug)]
+pub struct Backend {
+    pub host: String,
+    pub weight: u32,
+    active: AtomicUsize,
+    healthy: AtomicBool,
+}
+
+impl Backend {
+    /// Requests currently in flight to this backend
+    pub fn active_requests(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Backends are healthy until health checks tell otherwise
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed)
+    }
+}
+
+/// Virtual nodes per weight unit on the consistent hashing ring
+const RING_REPLICAS: u32 = 100;
+
+/// A named pool of backends sharing the load of a route
+#[derive(Debug)]
+pub struct Upstream {
+    pub name: String,
+    pub strategy: Strategy,
+    pub backends: Vec<Backend>,
+    pub health_check: Option<HealthCheckConfig>,
+    counter: AtomicUsize,
+    // Sorted (hash, backend index), only filled for consistent hashing
+    ring: Vec<(u64, usize)>,
+    checking: AtomicBool,
+}
+
+impl Upstream {
+    pub fn new(name: &str, config: &UpstreamConfig) -> Self {
+        let backends: Vec<Backend> = config
+            .backends
+            .iter()
+            .map(|backend| Backend {
+                host: backend.host.clone(),
+                weight: backend.weight,
+                active: AtomicUsize::new(0),
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+
+        let mut ring = vec![];
+        if let Strategy::ConsistentHash { .. } = config.strategy {
+            for (index, backend) in backends.iter().enumerate() {
+                for replica in 0..backend.weight * RING_REPLICAS {
+                    let node = format!("{}#{}", backend.host, replica);
+                    ring.push((hash(node.as_bytes()), index));
+                }
+            }
+            ring.sort_unstable();
+        }
+
+        Upstream {
+            name: name.to_string(),
+            strategy: config.strategy.clone(),
+            backends,
+            health_check: config.health_check.clone(),
+            counter: AtomicUsize::new(0),
+            ring,
+            checking: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts probing the backends, once, with the upstream request settings returned by `settings`.
+    ///
+    /// Must be called within the proxy runtime, probes stop once the upstream is dropped.
+    pub(crate) fn start_health_check<F>(self: &Arc<Self>, clients: &SharedClients, settings: F)
+    where
+        F: FnOnce() -> State,
+    {
+        if let Some(config) = &self.health_check {
+            // Only read by routed requests once the probes run
+            if !self.checking.load(Ordering::Relaxed)
+                && !self.checking.swap(true, Ordering::Relaxed)
+            {
+                tokio::spawn(health_check::run(
+                    Arc::downgrade(self),
+                    config.clone(),
+                    Arc::clone(clients),
+                    settings(),
+                ));
+            }
+        }
+    }
+
+    /// Picks a healthy backend for the request, `None` if the upstream has none
+    pub fn select(
+        self: &Arc<Self>,
+        req: &Request<Body>,
+        context: &ServiceContext,
+    ) -> Option<SelectedBackend> {
+        // Health checks may mark backends down meanwhile, strategies pick among this snapshot
+        let healthy = self.healthy_backends();
+        if healthy.is_empty() {
+            return None;
+        }
+        let index = self.pick(&healthy, req, context)?;
+
+        self.backends[index].active.fetch_add(1, Ordering::Relaxed);
+
+        Some(SelectedBackend {
+            in_flight: Arc::new(InFlight {
+                upstream: Arc::clone(self),
+                index,
+            }),
+        })
+    }
+
+    /// Indices of the healthy backends, in order
+    fn healthy_backends(&self) -> Vec<usize> {
+        (0..self.backends.len())
+            .filter(|&index| self.backends[index].is_healthy())
+            .collect()
+    }
+
+    fn pick(
+        &self,
+        healthy: &[usize],
+        req: &Request<Body>,
+        context: &ServiceContext,
+    ) -> Option<usize> {
+        match &self.strategy {
+            Strategy::RoundRobin => self.round_robin(healthy),
+            Strategy::Weighted => self.weighted(healthy),
+            Strategy::LeastConnections => self.least_connections(healthy),
+            Strategy::RandomTwoChoices => self.random_two_choices(healthy),
+            Strategy::ConsistentHash { header } => {
+                let key = match header {
+                    Some(header) => req
+                        .headers()
+                        .get(header.as_str())
+                        .map(|value| value.as_bytes().to_vec()),
+                    None => None,
+                };
+                let key = key.unwrap_or_else(|| ip_bytes(context.remote_addr.ip()));
+                self.consistent_hash(healthy, &key)
+            }
+        }
+    }
+
+    fn next(&self) -> usize {
+        self.counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn round_robin(&self, healthy: &[usize]) -> Option<usize> {
+        // Unhealthy backends are skipped, the next healthy one takes their turn
+        let start = self.next() % self.backends.len().max(1);
+        healthy
+            .iter()
+            .find(|&&index| index >= start)
+            .or_else(|| healthy.first())
+            .copied()
+    }
+
+    fn weighted(&self, healthy: &[usize]) -> Option<usize> {
+        let total: u64 = healthy
+            .iter()
+            .map(|&index| u64::from(self.backends[index].weight))
+            .sum();
+        if total == 0 {
+            return self.round_robin(healthy);
+        }
+
+        let mut position = self.next() as u64 % total;
+        for &index in healthy {
+            let weight = u64::from(self.backends[index].weight);
+            if position < weight {
+                return Some(index);
+            }
+            position -= weight;
+        }
+        None
+    }
+
+    fn least_connections(&self, healthy: &[usize]) -> Option<usize> {
+        // Ties are broken in turn so idle backends share the load
+        let start = self.next();
+        let len = healthy.len();
+        (0..len)
+            .map(|offset| healthy[(start + offset) % len])
+            .min_by_key(|&index| self.backends[index].active_requests())
+    }
+
+    fn random_two_choices(&self, healthy: &[usize]) -> Option<usize> {
+        let mut rng = rand::thread_rng();
+        let first = *healthy.choose(&mut rng)?;
+        let second = *healthy.choose(&mut rng)?;
+        if self.backends[second].active_requests() < self.backends[first].active_requests() {
+            Some(second)
+        } else {
+            Some(first)
+        }
+    }
+
+    fn consistent_hash(&self, healthy: &[usize], key: &[u8]) -> Option<usize> {
+        if self.ring.is_empty() {
+            return self.round_robin(healthy);
+        }
+
+        // Keys of an unhealthy backend move to the next healthy one on the ring,
+        // other keys keep their backend
+        let key_hash = hash(key);
+        let position = self.ring.partition_point(|&(node, _)| node < key_hash);
+        let len = self.ring.len();
+        (0..len)
+            .map(|offset| self.ring[(position + offset) % len].1)
+            .find(|index| healthy.binary_search(index).is_ok())
+            .or_else(|| self.round_robin(healthy))
+    }
+}
+
+/// Backend chosen for a request, stored in the request `State`.
+///
+/// Counts as a request in flight for the backend until the response body is sent
+/// or the upgraded connection closed, and every clone is dropped.
+#[derive(Clone, Debug)]
+pub struct SelectedBackend {
+    in_flight: Arc<InFlight>,
+}
+
+impl SelectedBackend {
+    pub fn upstream(&self) -> &Upstream {
+        &self.in_flight.upstream
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.upstream().backends[self.in_flight.index]
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    upstream: Arc<Upstream>,
+    index: usize,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.upstream.backends[self.index]
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// Stable across runs and platforms, unlike the std hasher.
+/// FNV-1a followed by the murmur3 finalizer to spread close keys on the ring.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(strategy: Strategy) -> Arc<Upstream> {
+        let backend = |host: &str| BackendConfig {
+            host: host.to_string(),
+            weight: 1,
+        };
+        Arc::new(Upstream::new(
+            "pool",
+            &UpstreamConfig {
+                strategy,
+                backends: vec![backend("a:80"), backend("b:80")],
+                health_check: None,
+            },
+        ))
+    }
+
+    fn context() -> ServiceContext {
+        ServiceContext {
+            remote_addr: ([127, 0, 0, 1], 4242).into(),
+            local_addr: None,
+            tls: false,
+            req_id: String::new(),
+            circuit: None,
+        }
+    }
+
+    #[test]
+    fn backends_marked_down_after_the_health_check_are_still_picked_from_the_snapshot() {
+        let strategies = vec![
+            Strategy::RoundRobin,
+            Strategy::Weighted,
+            Strategy::LeastConnections,
+            Strategy::RandomTwoChoices,
+            Strategy::ConsistentHash { header: None },
+        ];
+        let req = Request::new(Body::empty());
+        for strategy in strategies {
+            let upstream = upstream(strategy.clone());
+            let healthy = upstream.healthy_backends();
+
+            // A health check marks every backend down before the pick
+            for backend in &upstream.backends {
+                backend.set_healthy(false);
+            }
+            assert!(
+                upstream.pick(&healthy, &req, &context()).is_some(),
+                "{:?}",
+                strategy
+            );
+            assert!(
+                upstream.select(&req, &context()).is_none(),
+                "{:?}",
+                strategy
+            );
+            assert!(
+                upstream.pick(&[], &req, &context()).is_none(),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::RouterFormat;
+
+#[cfg(feature = "https")]
+use crate::tls::TlsError;
+
+/// Why a Router config was rejected, rule indexes start at 0
+#[derive(Debug)]
+pub enum RouterError {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    /// Invalid syntax, or a document not shaped like a Router config.
+    ///
+    /// `line` and `column` start at 1, they are 0 when the parser gives no position.
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The format feature is not enabled
+    UnsupportedFormat {
+        path: String,
+        format: RouterFormat,
+    },
+    InvalidRule {
+        path: String,
+        index: usize,
+        source: serde_json::Error,
+    },
+    /// `field` is one of `from.host`, `from.path`, `to.host` and `to.path`
+    InvalidRegex {
+        path: String,
+        index: usize,
+        field: &'static str,
+        pattern: String,
+        source: regex::Error,
+    },
+    UnknownUpstream {
+        path: String,
+        index: usize,
+        upstream: String,
+    },
+    #[cfg(feature = "https")]
+    InvalidUpstreamTls {
+        path: String,
+        index: usize,
+        source: TlsError,
+    },
+    /// The rule can never match, every request it matches is matched by an earlier rule first
+    ShadowedRule {
+        path: String,
+        index: usize,
+        shadowed_by: usize,
+    },
+}
+
+impl RouterError {
+    /// Config file the error comes from
+    pub fn path(&self) -> &str {
+        match self {
+            RouterError::Io { path, .. }
+            | RouterError::Parse { path, .. }
+            | RouterError::UnsupportedFormat { path, .. }
+            | RouterError::InvalidRule { path, .. }
+            | RouterError::InvalidRegex { path, .. }
+            | RouterError::UnknownUpstream { path, .. }
+            | RouterError::ShadowedRule { path, .. } => path,
+            #[cfg(feature = "https")]
+            RouterError::InvalidUpstreamTls { path, .. } => path,
+        }
+    }
+
+    /// Index of the invalid rule, if the error is about a single rule
+    pub fn rule_index(&self) -> Option<usize> {
+        match self {
+            RouterError::InvalidRule { index, .. }
+            | RouterError::InvalidRegex { index, .. }
+            | RouterError::UnknownUpstream { index, .. }
+            | RouterError::ShadowedRule { index, .. } => Some(*index),
+            #[cfg(feature = "https")]
+            RouterError::InvalidUpstreamTls { index, .. } => Some(*index),
+            RouterError::Io { .. }
+            | RouterError::Parse { .. }
+            | RouterError::UnsupportedFormat { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouterError::Io { path, source } => write!(f, "cannot read {}: {}", path, source),
+            RouterError::Parse { path, source, .. } => {
+                write!(f, "cannot parse {}: {}", path, source)
+            }
+            RouterError::UnsupportedFormat { path, format } => {
+                let feature = match format {
+                    RouterFormat::Json => "router",
+                    RouterFormat::Yaml => "yaml",
+                    RouterFormat::Toml => "toml",
+                };
+                write!(
+                    f,
+                    "cannot parse {}: {:?} configs need the `{}` feature",
+                    path, format, feature
+                )
+            }
+            RouterError::InvalidRule {
+                path,
+                index,
+                source,
+            } => write!(f, "invalid rule {} in {}: {}", index, path, source),
+            RouterError::InvalidRegex {
+                path,
+                index,
+                field,
+                pattern,
+                source,
+            } => write!(
+                f,
+                "invalid regex {:?} in {} of rule {} in {}: {}",
+                pattern, field, index, path, source
+            ),
+            RouterError::UnknownUpstream {
+                path,
+                index,
+                upstream,
+            } => write!(
+                f,
+                "rule {} in {} uses the unknown upstream {}",
+                index, path, upstream
+            ),
+            #[cfg(feature = "https")]
+            RouterError::InvalidUpstreamTls {
+                path,
+                index,
+                source,
+            } => write!(
+                f,
+                "invalid upstream TLS config of rule {} in {}: {}",
+                index, path, source
+            ),
+            RouterError::ShadowedRule {
+                path,
+                index,
+                shadowed_by,
+            } => write!(
+                f,
+                "rule {} in {} is unreachable, rule {} matches its requests first",
+                index, path, shadowed_by
+            ),
+        }
+    }
+}
+
+impl Error for RouterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RouterError::Io { source, .. } => Some(source),
+            RouterError::Parse { source, .. } => Some(source.as_ref()),
+            RouterError::InvalidRule { source, .. } => Some(source),
+            RouterError::InvalidRegex { source, .. } => Some(source),
+            #[cfg(feature = "https")]
+            RouterError::InvalidUpstreamTls { source, .. } => Some(source),
+            RouterError::UnsupportedFormat { .. }
+            | RouterError::UnknownUpstream { .. }
+            | RouterError::ShadowedRule { .. } => None,
+        }
+    }
+}
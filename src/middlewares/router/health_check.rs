@@ -0,0 +1,140 @@
+use hyper::{Body, Request};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+use super::balancer::Upstream;
+use super::split_scheme;
+use crate::proxy::client::Clients;
+use crate::proxy::service::State;
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+fn default_healthy_threshold() -> u32 {
+    2
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// How a backend is probed
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// The backend is healthy when it accepts TCP connections
+    Tcp,
+    /// The backend is healthy when `GET path` answers with `status`
+    Http {
+        path: String,
+        #[serde(default = "default_status")]
+        status: u16,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthCheckConfig {
+    pub probe: Probe,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Consecutive failed probes marking a healthy backend unhealthy
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+    /// Consecutive successful probes marking an unhealthy backend healthy
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+}
+
+/// Clients probing backends, the Router ones until `Router::set_clients` replaces them
+pub(crate) type SharedClients = Arc<RwLock<Arc<Clients>>>;
+
+/// Probes every backend of the upstream each interval, until the upstream is dropped.
+///
+/// HTTP probes are sent with the `UpstreamVersion` and `UpstreamTls` found in `settings`.
+pub(crate) async fn run(
+    upstream: Weak<Upstream>,
+    config: HealthCheckConfig,
+    clients: SharedClients,
+    settings: State,
+) {
+    let interval = Duration::from_millis(config.interval_ms);
+    let timeout = Duration::from_millis(config.timeout_ms);
+
+    // Consecutive successes (positive) or failures (negative) of each backend
+    let mut streaks: Vec<i64> = vec![];
+
+    loop {
+        let upstream: Arc<Upstream> = match upstream.upgrade() {
+            Some(upstream) => upstream,
+            None => return,
+        };
+        streaks.resize(upstream.backends.len(), 0);
+        let client = clients.read().unwrap().for_request(&settings);
+
+        for (backend, streak) in upstream.backends.iter().zip(streaks.iter_mut()) {
+            let (scheme, host) = split_scheme(&backend.host);
+            let probe = async {
+                match &config.probe {
+                    Probe::Tcp => {
+                        let port = if scheme == "https" { 443 } else { 80 };
+                        let addr = if host.contains(':') {
+                            host.to_string()
+                        } else {
+                            format!("{}:{}", host, port)
+                        };
+                        TcpStream::connect(addr).await.is_ok()
+                    }
+                    Probe::Http { path, status } => {
+                        let req = Request::get(format!("{}://{}{}", scheme, host, path))
+                            .header("host", host)
+                            .body(Body::empty());
+                        match req {
+                            Ok(req) => match client.request(req).await {
+                                Ok(res) => res.status().as_u16() == *status,
+                                Err(_) => false,
+                            },
+                            Err(_) => false,
+                        }
+                    }
+                }
+            };
+            let success = tokio::time::timeout(timeout, probe).await.unwrap_or(false);
+
+            *streak = match (success, *streak) {
+                (true, streak) if streak > 0 => streak + 1,
+                (true, _) => 1,
+                (false, streak) if streak < 0 => streak - 1,
+                (false, _) => -1,
+            };
+
+            if backend.is_healthy() && -*streak >= i64::from(config.unhealthy_threshold) {
+                warn!(
+                    "Backend {} of upstream {} is unhealthy",
+                    backend.host, upstream.name
+                );
+                backend.set_healthy(false);
+            } else if !backend.is_healthy() && *streak >= i64::from(config.healthy_threshold) {
+                info!(
+                    "Backend {} of upstream {} is healthy again",
+                    backend.host, upstream.name
+                );
+                backend.set_healthy(true);
+            }
+        }
+
+        drop(upstream);
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,155 @@
+use hyper::{Body, Request};
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::proxy::cidr::Cidr;
+use crate::proxy::service::ServiceContext;
+
+/// Requests a route applies to, every condition given must hold
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteFrom {
+    #[serde(with = "serde_regex")]
+    pub host: Regex,
+    #[serde(with = "serde_regex")]
+    pub path: Regex,
+    /// Any of these methods, e.g. `["GET", "HEAD"]`
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, ValueMatch>,
+    #[serde(default)]
+    pub query: HashMap<String, ValueMatch>,
+    /// Client IP in any of these ranges, e.g. `["10.0.0.0/8"]`
+    #[serde(default)]
+    pub client_ips: Vec<Cidr>,
+    /// Request received on any of these listener ports
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+/// Condition on a header or query parameter: `"value"`, `{ "regex": "^v[23]$" }`
+/// or `{ "present": false }`. Headers and parameters set several times match when any value does.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ValueMatch {
+    Exact(String),
+    Regex {
+        #[serde(with = "serde_regex")]
+        regex: Regex,
+    },
+    Present {
+        present: bool,
+    },
+}
+
+impl ValueMatch {
+    /// `values` are every value of the header or parameter, `None` for values that are not UTF-8
+    fn matches<'a>(&self, mut values: impl Iterator<Item = Option<&'a str>>) -> bool {
+        match self {
+            ValueMatch::Exact(expected) => values.any(|value| value == Some(expected.as_str())),
+            ValueMatch::Regex { regex } => {
+                values.any(|value| value.is_some_and(|value| regex.is_match(value)))
+            }
+            ValueMatch::Present { present } => values.next().is_some() == *present,
+        }
+    }
+}
+
+impl RouteFrom {
+    /// Whether the route has conditions besides host and path
+    pub fn has_conditions(&self) -> bool {
+        !self.methods.is_empty()
+            || !self.headers.is_empty()
+            || !self.query.is_empty()
+            || !self.client_ips.is_empty()
+            || !self.ports.is_empty()
+    }
+
+    /// Whether the request meets the conditions besides host and path
+    pub fn matches(&self, req: &Request<Body>, context: &ServiceContext) -> bool {
+        if !self.methods.is_empty()
+            && !self
+                .methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(req.method().as_str()))
+        {
+            return false;
+        }
+
+        for (name, condition) in &self.headers {
+            let values = req.headers().get_all(name.as_str()).iter();
+            if !condition.matches(values.map(|value| value.to_str().ok())) {
+                return false;
+            }
+        }
+
+        if !self.query.is_empty() {
+            let pairs: Vec<(String, String)> =
+                query_pairs(req.uri().query().unwrap_or("")).collect();
+            for (name, condition) in &self.query {
+                let values = pairs
+                    .iter()
+                    .filter(|(key, _)| key == name)
+                    .map(|(_, value)| Some(value.as_str()));
+                if !condition.matches(values) {
+                    return false;
+                }
+            }
+        }
+
+        if !self.client_ips.is_empty()
+            && !self
+                .client_ips
+                .iter()
+                .any(|range| range.contains(context.remote_addr.ip()))
+        {
+            return false;
+        }
+
+        if !self.ports.is_empty()
+            && !context
+                .local_addr
+                .is_some_and(|addr| self.ports.contains(&addr.port()))
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Decoded `name=value` pairs of a query string
+pub(crate) fn query_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+}
+
+/// Percent-decodes a query component, `+` being a space
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', None) => decoded.push(b' '),
+            (byte, None) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,624 @@
+pub mod balancer;
+pub mod error;
+pub mod health_check;
+pub mod matcher;
+pub mod rewrite;
+
+use http::uri::{Parts, Uri};
+use hyper::header::HeaderValue;
+use hyper::{Body, Request, StatusCode};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use self::balancer::{Upstream, UpstreamConfig};
+pub use self::error::RouterError;
+use self::health_check::SharedClients;
+use self::matcher::RouteFrom;
+use self::rewrite::RewriteAction;
+
+use crate::middlewares::headers::HeadersConfig;
+use crate::proxy::client::{Clients, UpstreamTimeouts, UpstreamVersion};
+#[cfg(feature = "https")]
+use crate::proxy::client::{UpstreamTls, UpstreamTlsOptions};
+use crate::proxy::error::MiddlewareError;
+use crate::proxy::middleware::MiddlewareResult::Next;
+use crate::proxy::middleware::{Middleware, MiddlewareResult};
+use crate::proxy::retry::RetryPolicy;
+use crate::proxy::service::{ExchangeGuards, ServiceContext, State};
+
+use serde_json;
+
+/// Clones share their rules, reloading one reloads them all
+#[derive(Clone)]
+pub struct Router {
+    path: String,
+    format: RouterFormat,
+    routes: Arc<RwLock<Arc<RouterRules>>>,
+    clients: SharedClients,
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .field("routes", &self.routes)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteRegex {
+    #[serde(with = "serde_regex")]
+    pub host: Regex,
+    #[serde(with = "serde_regex")]
+    pub path: Regex,
+}
+
+/// `to.host` can start with `https://` to reach the upstream over TLS, `http://` is the default
+#[derive(Debug, Clone, Deserialize)]
+pub struct Route {
+    pub from: RouteFrom,
+    pub to: RouteRegex,
+    pub public: bool,
+    /// Applied in order to the path built from `to.path`
+    #[serde(default)]
+    pub rewrite: Vec<RewriteAction>,
+    /// Name of an upstream pool in `upstreams`, its backends replace the `to.host` target
+    pub upstream: Option<String>,
+    #[serde(skip)]
+    pool: Option<Arc<Upstream>>,
+    /// Reach the upstream over HTTP/2 (e.g. gRPC services)
+    #[serde(default)]
+    pub http2: bool,
+    /// Retries failed idempotent requests, omitted fields take their default value
+    pub retry: Option<RetryPolicy>,
+    /// Overrides the proxy upstream timeouts, field by field
+    pub timeouts: Option<UpstreamTimeouts>,
+    /// Header changes applied by the `Headers` middleware after its own
+    pub headers: Option<HeadersConfig>,
+    #[cfg(feature = "https")]
+    pub tls: Option<UpstreamTlsOptions>,
+    #[cfg(feature = "https")]
+    #[serde(skip)]
+    upstream_tls: Option<UpstreamTls>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouterRulesWrapper {
+    pub rules: RouterRules,
+    #[serde(default)]
+    pub upstreams: HashMap<String, UpstreamConfig>,
+}
+
+pub type RouterRules = Vec<Route>;
+
+impl Route {
+    /// HTTP version and TLS config of the requests sent to the upstream
+    fn upstream_settings(&self) -> State {
+        let mut settings = State::new();
+        if self.http2 {
+            settings.insert(UpstreamVersion::Http2);
+        }
+        #[cfg(feature = "https")]
+        {
+            if let Some(tls) = &self.upstream_tls {
+                settings.insert(tls.clone());
+            }
+        }
+        settings
+    }
+}
+
+/// Stored in the request `State` once a route matched
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchedRoute {
+    pub uri: String,
+    pub public: bool,
+}
+
+pub trait RouterConfig {
+    fn get_router_filename(&self) -> &str;
+
+    /// Format of the config file, guessed from its extension by default
+    fn get_router_format(&self) -> Option<RouterFormat> {
+        None
+    }
+}
+
+/// Syntax of a Router config file, YAML and TOML need the `yaml` and `toml` features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl RouterFormat {
+    /// `.yaml` and `.yml` files are YAML, `.toml` files are TOML and the others JSON
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => RouterFormat::Yaml,
+            Some("toml") => RouterFormat::Toml,
+            _ => RouterFormat::Json,
+        }
+    }
+}
+
+fn get_host_and_path(req: &mut Request<Body>) -> Result<(String, String), MiddlewareError> {
+    let uri = req.uri();
+    let path = uri
+        .path_and_query()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| String::from(""));
+
+    match uri.host() {
+        Some(host) => Ok((String::from(host), path)),
+        None => match req.headers().get("host") {
+            Some(host) => Ok((String::from(host.to_str()?), path)),
+            None => Err(MiddlewareError::new(
+                String::from("No host in the request"),
+                Some(String::from("Bad request")),
+                StatusCode::BAD_REQUEST,
+            )),
+        },
+    }
+}
+
+fn split_scheme(host: &str) -> (&str, &str) {
+    if let Some(host) = host.strip_prefix("https://") {
+        ("https", host)
+    } else {
+        ("http", host.strip_prefix("http://").unwrap_or(host))
+    }
+}
+
+fn inject_new_uri(
+    req: &mut Request<Body>,
+    old_host: &str,
+    scheme: &str,
+    host: &str,
+    path: &str,
+) -> Result<(), MiddlewareError> {
+    {
+        let headers = req.headers_mut();
+
+        headers.insert("X-Forwarded-Host", HeaderValue::from_str(old_host)?);
+        headers.insert("host", HeaderValue::from_str(host)?);
+    }
+    let mut parts = Parts::default();
+    parts.scheme = Some(scheme.parse()?);
+    parts.authority = Some(host.parse()?);
+    parts.path_and_query = Some(path.parse()?);
+
+    debug!("Found a route to {:?}", parts);
+
+    *req.uri_mut() = Uri::from_parts(parts)?;
+
+    Ok(())
+}
+
+impl Middleware for Router {
+    fn name() -> String {
+        String::from("Router")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        context: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        // Requests keep the rules they started with when rules are reloaded meanwhile
+        let routes = Arc::clone(&self.routes.read().unwrap());
+
+        let (host, path) = get_host_and_path(req)?;
+        debug!("Routing => Host: {} Path: {}", host, path);
+
+        for route in routes.iter() {
+            let (re_host, re_path) = (&route.from.host, &route.from.path);
+            let to = &route.to;
+            let public = route.public;
+
+            debug!("Trying to convert from {} / {:?}", &re_host, &re_path);
+
+            if re_host.is_match(&host) {
+                let new_host = re_host.replace(&host, to.host.as_str());
+
+                let new_path = if re_path.is_match(&path) {
+                    re_path.replace(&path, to.path.as_str())
+                } else {
+                    continue;
+                };
+                if !route.from.matches(req, context) {
+                    continue;
+                }
+                let new_path = if route.rewrite.is_empty() {
+                    new_path.into_owned()
+                } else {
+                    rewrite::rewrite(&route.rewrite, &new_path)
+                };
+
+                if let Some(pool) = &route.pool {
+                    // Checks start now when there was no runtime to start them with the rules
+                    pool.start_health_check(&self.clients, || route.upstream_settings());
+                }
+                let new_host = match &route.pool {
+                    Some(pool) => match pool.select(req, context) {
+                        Some(selected) => {
+                            let host = selected.backend().host.clone();
+                            ExchangeGuards::hold(state, selected.clone());
+                            state.insert(selected);
+                            host
+                        }
+                        None => {
+                            return Err(MiddlewareError::new(
+                                format!("No backend available in upstream {}", pool.name),
+                                Some(String::from("Service unavailable")),
+                                StatusCode::SERVICE_UNAVAILABLE,
+                            ))
+                        }
+                    },
+                    None => new_host.into_owned(),
+                };
+                let (scheme, new_host) = split_scheme(&new_host);
+
+                #[cfg(not(feature = "https"))]
+                {
+                    if scheme == "https" {
+                        return Err(MiddlewareError::new(
+                            String::from("HTTPS upstreams require the `https` feature"),
+                            None,
+                            StatusCode::BAD_GATEWAY,
+                        ));
+                    }
+                }
+                if route.http2 {
+                    state.insert(UpstreamVersion::Http2);
+                }
+                if let Some(retry) = &route.retry {
+                    state.insert(retry.clone());
+                }
+                if let Some(timeouts) = route.timeouts {
+                    state.insert(timeouts);
+                }
+                if let Some(headers) = &route.headers {
+                    state.insert(headers.clone());
+                }
+                #[cfg(feature = "https")]
+                {
+                    if let Some(tls) = &route.upstream_tls {
+                        state.insert(tls.clone());
+                    }
+                }
+
+                debug!("Proxying to {}://{}", scheme, new_host);
+                inject_new_uri(req, &host, scheme, new_host, &new_path)?;
+                state.insert(MatchedRoute {
+                    uri: req.uri().to_string(),
+                    public,
+                });
+                return Ok(Next);
+            }
+        }
+
+        Err(MiddlewareError::new(
+            String::from("No route matched"),
+            Some(String::from("Not found")),
+            StatusCode::NOT_FOUND,
+        ))
+    }
+}
+
+/// Rules are kept as JSON values until every one of them is checked on its own
+#[derive(Deserialize)]
+struct RawRules {
+    rules: Vec<serde_json::Value>,
+    #[serde(default)]
+    upstreams: HashMap<String, UpstreamConfig>,
+}
+
+const REGEX_FIELDS: [(&str, &str, &str); 4] = [
+    ("from.host", "from", "host"),
+    ("from.path", "from", "path"),
+    ("to.host", "to", "host"),
+    ("to.path", "to", "path"),
+];
+
+fn check_regexes(path: &str, index: usize, rule: &serde_json::Value) -> Result<(), RouterError> {
+    for (field, part, key) in REGEX_FIELDS {
+        let pattern = rule
+            .get(part)
+            .and_then(|part| part.get(key))
+            .and_then(serde_json::Value::as_str);
+        if let Some(pattern) = pattern {
+            Regex::new(pattern).map_err(|source| RouterError::InvalidRegex {
+                path: path.to_string(),
+                index,
+                field,
+                pattern: pattern.to_string(),
+                source,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn matches_everything(re: &Regex) -> bool {
+    let pattern = re.as_str();
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = pattern.strip_suffix('$').unwrap_or(pattern);
+    matches!(pattern, ".*" | "(.*)")
+}
+
+/// Whether every request matched by `later` is matched by `earlier` first
+fn shadows(earlier: &RouteFrom, later: &RouteFrom) -> bool {
+    let covers = |a: &Regex, b: &Regex| matches_everything(a) || a.as_str() == b.as_str();
+    !earlier.has_conditions()
+        && covers(&earlier.host, &later.host)
+        && covers(&earlier.path, &later.path)
+}
+
+/// One-based line and column of the byte at `offset`
+#[cfg(feature = "toml")]
+fn line_column(data: &str, offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn parse_rules(path: &str, format: RouterFormat, data: &str) -> Result<RawRules, RouterError> {
+    let parse_error = |line, column, source| RouterError::Parse {
+        path: path.to_string(),
+        line,
+        column,
+        source,
+    };
+
+    match format {
+        RouterFormat::Json => serde_json::from_str(data)
+            .map_err(|err| parse_error(err.line(), err.column(), Box::new(err))),
+        // serde_yaml expects YAML tags for enums, going through JSON values keeps the JSON shape
+        #[cfg(feature = "yaml")]
+        RouterFormat::Yaml => serde_yaml::from_str(data)
+            .map_err(|err| {
+                let (line, column) = err
+                    .location()
+                    .map_or((0, 0), |location| (location.line(), location.column()));
+                parse_error(line, column, Box::new(err))
+            })
+            .and_then(|value| {
+                serde_json::from_value(value).map_err(|err| parse_error(0, 0, Box::new(err)))
+            }),
+        #[cfg(feature = "toml")]
+        RouterFormat::Toml => toml::from_str(data).map_err(|err| {
+            let (line, column) = err
+                .span()
+                .map_or((0, 0), |span| line_column(data, span.start));
+            parse_error(line, column, Box::new(err))
+        }),
+        #[allow(unreachable_patterns)]
+        format => Err(RouterError::UnsupportedFormat {
+            path: path.to_string(),
+            format,
+        }),
+    }
+}
+
+fn read_routes(path: &str, format: RouterFormat) -> Result<RouterRules, RouterError> {
+    let data = std::fs::read_to_string(path).map_err(|source| RouterError::Io {
+        path: path.to_string(),
+        source,
+    })?;
+
+    let raw = parse_rules(path, format, &data)?;
+
+    let pools: HashMap<&String, Arc<Upstream>> = raw
+        .upstreams
+        .iter()
+        .map(|(name, config)| (name, Arc::new(Upstream::new(name, config))))
+        .collect();
+
+    let mut rules = RouterRules::with_capacity(raw.rules.len());
+    for (index, rule) in raw.rules.into_iter().enumerate() {
+        check_regexes(path, index, &rule)?;
+        let mut route: Route =
+            serde_json::from_value(rule).map_err(|source| RouterError::InvalidRule {
+                path: path.to_string(),
+                index,
+                source,
+            })?;
+
+        if let Some(headers) = &route.headers {
+            headers.validate().map_err(|err| RouterError::InvalidRule {
+                path: path.to_string(),
+                index,
+                source: serde::de::Error::custom(format!("headers: {}", err)),
+            })?;
+        }
+
+        if let Some(name) = &route.upstream {
+            let pool = pools
+                .get(name)
+                .ok_or_else(|| RouterError::UnknownUpstream {
+                    path: path.to_string(),
+                    index,
+                    upstream: name.clone(),
+                })?;
+            route.pool = Some(Arc::clone(pool));
+        }
+
+        #[cfg(feature = "https")]
+        {
+            if let Some(options) = &route.tls {
+                route.upstream_tls = Some(UpstreamTls::new(options).map_err(|source| {
+                    RouterError::InvalidUpstreamTls {
+                        path: path.to_string(),
+                        index,
+                        source,
+                    }
+                })?);
+            }
+        }
+
+        if let Some(shadowed_by) = rules
+            .iter()
+            .position(|earlier: &Route| shadows(&earlier.from, &route.from))
+        {
+            return Err(RouterError::ShadowedRule {
+                path: path.to_string(),
+                index,
+                shadowed_by,
+            });
+        }
+        rules.push(route);
+    }
+
+    Ok(rules)
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl Router {
+    /// Panics when the config is invalid, see `try_new`
+    pub fn new<T: RouterConfig>(config: &T) -> Self {
+        Router::try_new(config).unwrap_or_else(|err| panic!("Invalid Router config: {}", err))
+    }
+
+    /// Reads and validates the config file, rules shadowed by earlier ones are rejected.
+    ///
+    /// Upstream health checks start right away within a runtime, with the first request otherwise.
+    pub fn try_new<T: RouterConfig>(config: &T) -> Result<Self, RouterError> {
+        let path = config.get_router_filename().to_string();
+        let format = config
+            .get_router_format()
+            .unwrap_or_else(|| RouterFormat::from_path(&path));
+        let routes = read_routes(&path, format)?;
+        let router = Router {
+            path,
+            format,
+            routes: Arc::new(RwLock::new(Arc::new(routes))),
+            clients: Arc::new(RwLock::new(Arc::new(Clients::new()))),
+        };
+        router.start_health_checks();
+        Ok(router)
+    }
+
+    /// Probes upstream backends with `clients`, e.g. `SimpleProxy::clients()` so probes
+    /// share the proxy connection pools. The router has clients of its own otherwise.
+    pub fn set_clients(&self, clients: Arc<Clients>) {
+        *self.clients.write().unwrap() = clients;
+    }
+
+    /// Upstreams are probed with the HTTP version and TLS config of the first route sending to them
+    fn start_health_checks(&self) {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return;
+        }
+        for route in self.rules().iter() {
+            if let Some(pool) = &route.pool {
+                pool.start_health_check(&self.clients, || route.upstream_settings());
+            }
+        }
+    }
+
+    /// Rules currently used for new requests
+    pub fn rules(&self) -> Arc<RouterRules> {
+        Arc::clone(&self.routes.read().unwrap())
+    }
+
+    /// Reads the config file again and swaps the rules in for new requests.
+    ///
+    /// The current rules are kept when the new ones are invalid.
+    pub fn reload(&self) -> Result<(), RouterError> {
+        match read_routes(&self.path, self.format) {
+            Ok(routes) => {
+                *self.routes.write().unwrap() = Arc::new(routes);
+                info!("Router config {} reloaded", self.path);
+                self.start_health_checks();
+                Ok(())
+            }
+            Err(err) => {
+                error!(
+                    "Router config not reloaded, keeping the current rules: {}",
+                    err
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Reloads the rules whenever the config file is modified, checking it every `interval`.
+    ///
+    /// Must be called within the proxy runtime, stops once every clone of the router is dropped.
+    pub fn watch(&self, interval: Duration) {
+        let (path, format) = (self.path.clone(), self.format);
+        let routes = Arc::downgrade(&self.routes);
+        let clients = Arc::clone(&self.clients);
+        let mut last_modified = modified(&path);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let routes = match routes.upgrade() {
+                    Some(routes) => routes,
+                    None => return,
+                };
+
+                let modified = modified(&path);
+                if modified != last_modified {
+                    last_modified = modified;
+                    let router = Router {
+                        path: path.clone(),
+                        format,
+                        routes,
+                        clients: Arc::clone(&clients),
+                    };
+                    let _ = router.reload();
+                }
+            }
+        });
+    }
+
+    /// Reloads the rules when the process receives `SIGHUP`.
+    ///
+    /// Must be called within the proxy runtime, stops once every clone of the router is dropped.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&self) -> std::io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let (path, format) = (self.path.clone(), self.format);
+        let routes = Arc::downgrade(&self.routes);
+        let clients = Arc::clone(&self.clients);
+
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                let routes = match routes.upgrade() {
+                    Some(routes) => routes,
+                    None => return,
+                };
+                info!("SIGHUP received, reloading Router config");
+                let router = Router {
+                    path: path.clone(),
+                    format,
+                    routes,
+                    clients: Arc::clone(&clients),
+                };
+                let _ = router.reload();
+            }
+        });
+        Ok(())
+    }
+}
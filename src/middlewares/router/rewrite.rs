@@ -0,0 +1,124 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use super::matcher::query_pairs;
+
+/// A change made to the upstream path once `to.path` is applied, the query string
+/// is kept unless an action changes it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteAction {
+    /// Removes a leading path prefix, only when it ends on a segment boundary
+    StripPrefix(String),
+    AddPrefix(String),
+    /// Replaces the path matches of `regex`, `with` can refer to captures as `$1` or `$name`
+    Replace {
+        #[serde(with = "serde_regex")]
+        regex: Regex,
+        with: String,
+        /// Every match instead of the first one only
+        #[serde(default)]
+        all: bool,
+    },
+    /// Sets query parameters, replacing the values they had
+    SetQuery(HashMap<String, String>),
+    RemoveQuery(Vec<String>),
+}
+
+impl RewriteAction {
+    fn apply(&self, path: &mut String, query: &mut Vec<String>) {
+        match self {
+            RewriteAction::StripPrefix(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                if let Some(rest) = path.strip_prefix(prefix) {
+                    if rest.is_empty() || rest.starts_with('/') {
+                        *path = if rest.is_empty() {
+                            String::from("/")
+                        } else {
+                            rest.to_string()
+                        };
+                    }
+                }
+            }
+            RewriteAction::AddPrefix(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                *path = if path.starts_with('/') {
+                    format!("{}{}", prefix, path)
+                } else {
+                    format!("{}/{}", prefix, path)
+                };
+            }
+            RewriteAction::Replace { regex, with, all } => {
+                let replaced = if *all {
+                    regex.replace_all(path, with.as_str())
+                } else {
+                    regex.replace(path, with.as_str())
+                };
+                *path = replaced.into_owned();
+            }
+            RewriteAction::SetQuery(params) => {
+                let mut params: Vec<(&String, &String)> = params.iter().collect();
+                params.sort();
+                for (name, value) in params {
+                    remove_param(query, name);
+                    query.push(format!("{}={}", encode(name), encode(value)));
+                }
+            }
+            RewriteAction::RemoveQuery(names) => {
+                for name in names {
+                    remove_param(query, name);
+                }
+            }
+        }
+    }
+}
+
+/// Applies `actions` in order to `path_and_query`
+pub(crate) fn rewrite(actions: &[RewriteAction], path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let mut path = path.to_string();
+    // Raw `name=value` pairs, parameters left alone keep their encoding
+    let mut params: Vec<String> = query
+        .map(|query| {
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for action in actions {
+        action.apply(&mut path, &mut params);
+    }
+
+    if path.is_empty() {
+        path.push('/');
+    }
+    if params.is_empty() {
+        path
+    } else {
+        format!("{}?{}", path, params.join("&"))
+    }
+}
+
+fn remove_param(params: &mut Vec<String>, name: &str) {
+    params.retain(|pair| query_pairs(pair).next().is_none_or(|(key, _)| key != name));
+}
+
+/// Percent-encodes a query component, keeping unreserved characters only
+fn encode(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// State of the circuit of an upstream, as seen by the current request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through
+    Closed,
+    /// Requests are answered 503 without reaching the upstream
+    Open,
+    /// The cool-down elapsed, a single trial request decides whether the circuit closes
+    HalfOpen,
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive 5xx responses or connection errors opening the circuit
+    pub failure_threshold: u32,
+    /// Time the circuit stays open before a trial request is let through
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+    half_open: bool,
+}
+
+/// Circuits of every upstream host, shared by all the connections of the proxy
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreakers {
+            config,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn state(&self, upstream: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        match circuits.get(upstream) {
+            Some(Circuit {
+                half_open: true, ..
+            }) => CircuitState::HalfOpen,
+            Some(Circuit {
+                opened_at: Some(opened_at),
+                ..
+            }) if opened_at.elapsed() < self.config.cool_down => CircuitState::Open,
+            Some(Circuit {
+                opened_at: Some(_), ..
+            }) => CircuitState::HalfOpen,
+            _ => CircuitState::Closed,
+        }
+    }
+
+    /// Whether a request can be sent to `upstream`, `Open` when it cannot.
+    ///
+    /// Once the cool-down elapsed, a single request is let through in `HalfOpen`,
+    /// another one is only let through if it gave no result after another cool-down.
+    pub(crate) fn acquire(&self, upstream: &str) -> CircuitState {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = match circuits.get_mut(upstream) {
+            Some(circuit) => circuit,
+            None => return CircuitState::Closed,
+        };
+
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.config.cool_down => CircuitState::Open,
+            Some(_) => {
+                circuit.opened_at = Some(Instant::now());
+                circuit.half_open = true;
+                CircuitState::HalfOpen
+            }
+        }
+    }
+
+    /// Records the outcome of a request sent to `upstream`
+    pub(crate) fn record(&self, upstream: &str, success: bool) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if success {
+            if let Some(circuit) = circuits.remove(upstream) {
+                if circuit.opened_at.is_some() {
+                    info!("Circuit of {} closed", upstream);
+                }
+            }
+            return;
+        }
+
+        let circuit = circuits.entry(upstream.to_string()).or_default();
+        circuit.failures += 1;
+        if circuit.half_open
+            || (circuit.opened_at.is_none() && circuit.failures >= self.config.failure_threshold)
+        {
+            warn!(
+                "Circuit of {} opened after {} consecutive failures",
+                upstream, circuit.failures
+            );
+            circuit.opened_at = Some(Instant::now());
+            circuit.half_open = false;
+        }
+    }
+}
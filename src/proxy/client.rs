@@ -0,0 +1,450 @@
+use hyper::client::connect::HttpConnector;
+use hyper::Client;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(feature = "https")]
+use {
+    crate::tls::{self, TlsError},
+    hyper_rustls::{HttpsConnector, HttpsConnectorBuilder},
+    rustls::client::{ServerCertVerified, ServerCertVerifier},
+    rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    std::fmt,
+    std::sync::{Arc, Weak},
+    std::time::SystemTime,
+};
+
+use crate::proxy::service::State;
+
+#[cfg(feature = "https")]
+pub type Connector = HttpsConnector<HttpConnector>;
+#[cfg(not(feature = "https"))]
+pub type Connector = HttpConnector;
+
+/// HTTP version used to reach the upstream, insert it in the request `State` to change it.
+///
+/// `Http2` uses prior knowledge for `http://` upstreams (h2c) and ALPN for `https://` ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UpstreamVersion {
+    #[default]
+    Http1,
+    Http2,
+}
+
+/// Upstream timeouts, insert them in the request `State` to override the proxy ones field by field.
+///
+/// The total timeout covers retries and the response body, the client connection is closed
+/// when it elapses after the response started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "router", derive(Deserialize))]
+pub struct UpstreamTimeouts {
+    pub connect_ms: Option<u64>,
+    pub response_header_ms: Option<u64>,
+    pub total_ms: Option<u64>,
+}
+
+impl UpstreamTimeouts {
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect_ms.map(Duration::from_millis)
+    }
+
+    pub fn response_header(&self) -> Option<Duration> {
+        self.response_header_ms.map(Duration::from_millis)
+    }
+
+    pub fn total(&self) -> Option<Duration> {
+        self.total_ms.map(Duration::from_millis)
+    }
+
+    /// These timeouts, completed by `defaults`
+    pub fn or(&self, defaults: &UpstreamTimeouts) -> UpstreamTimeouts {
+        UpstreamTimeouts {
+            connect_ms: self.connect_ms.or(defaults.connect_ms),
+            response_header_ms: self.response_header_ms.or(defaults.response_header_ms),
+            total_ms: self.total_ms.or(defaults.total_ms),
+        }
+    }
+}
+
+/// TLS settings of an upstream, all PEM files
+#[cfg(feature = "https")]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpstreamTlsOptions {
+    /// CA bundle trusted instead of the default web roots
+    pub ca_file: Option<String>,
+    /// Client certificate chain, sent along `client_key` for mutual TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// Accepts any upstream certificate, only meant for development environments
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+/// TLS client configuration of an upstream.
+///
+/// Insert it in the request `State` to have the upstream request made with it,
+/// requests without it use the default web roots.
+#[cfg(feature = "https")]
+#[derive(Clone)]
+pub struct UpstreamTls(Arc<ClientConfig>);
+
+#[cfg(feature = "https")]
+impl UpstreamTls {
+    pub fn new(options: &UpstreamTlsOptions) -> Result<Self, TlsError> {
+        let roots = match &options.ca_file {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in tls::load_certificates(path)? {
+                    roots
+                        .add(&cert)
+                        .map_err(|_| TlsError::InvalidCertificate { path: path.clone() })?;
+                }
+                roots
+            }
+            None => web_roots(),
+        };
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut config = match (&options.client_cert, &options.client_key) {
+            (Some(cert_path), Some(key_path)) => builder
+                .with_client_auth_cert(
+                    tls::load_certificates(cert_path)?,
+                    tls::load_private_key(key_path)?,
+                )
+                .map_err(|_| TlsError::InvalidPrivateKey {
+                    path: key_path.clone(),
+                })?,
+            (Some(path), None) | (None, Some(path)) => {
+                return Err(TlsError::IncompleteClientAuth { path: path.clone() })
+            }
+            (None, None) => builder.with_no_client_auth(),
+        };
+
+        if options.insecure_skip_verify {
+            warn!("Upstream certificate verification is disabled");
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+
+        Ok(UpstreamTls(Arc::new(config)))
+    }
+
+    fn client(
+        &self,
+        pool: &PoolConfig,
+        version: UpstreamVersion,
+        connect_timeout: Option<Duration>,
+    ) -> Client<Connector> {
+        build_client(
+            pool,
+            connector(pool, (*self.0).clone(), version, connect_timeout),
+            version,
+        )
+    }
+
+    fn key(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    fn downgrade(&self) -> Weak<ClientConfig> {
+        Arc::downgrade(&self.0)
+    }
+}
+
+#[cfg(feature = "https")]
+impl fmt::Debug for UpstreamTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UpstreamTls").finish()
+    }
+}
+
+#[cfg(feature = "https")]
+struct NoVerification;
+
+#[cfg(feature = "https")]
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(feature = "https")]
+fn web_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    roots
+}
+
+fn http_connector(pool: &PoolConfig, connect_timeout: Option<Duration>) -> HttpConnector {
+    let mut http = HttpConnector::new();
+    http.set_connect_timeout(connect_timeout);
+    http.set_keepalive(pool.tcp_keepalive);
+    http.set_nodelay(pool.tcp_nodelay);
+    http
+}
+
+#[cfg(feature = "https")]
+fn connector(
+    pool: &PoolConfig,
+    config: ClientConfig,
+    version: UpstreamVersion,
+    connect_timeout: Option<Duration>,
+) -> Connector {
+    let mut http = http_connector(pool, connect_timeout);
+    http.enforce_http(false);
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http();
+    match version {
+        UpstreamVersion::Http1 => builder.enable_http1().wrap_connector(http),
+        UpstreamVersion::Http2 => builder.enable_http2().wrap_connector(http),
+    }
+}
+
+#[cfg(feature = "https")]
+fn default_connector(
+    pool: &PoolConfig,
+    version: UpstreamVersion,
+    connect_timeout: Option<Duration>,
+) -> Connector {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(web_roots())
+        .with_no_client_auth();
+    connector(pool, config, version, connect_timeout)
+}
+
+#[cfg(not(feature = "https"))]
+fn default_connector(
+    pool: &PoolConfig,
+    _version: UpstreamVersion,
+    connect_timeout: Option<Duration>,
+) -> Connector {
+    http_connector(pool, connect_timeout)
+}
+
+fn build_client(
+    pool: &PoolConfig,
+    connector: Connector,
+    version: UpstreamVersion,
+) -> Client<Connector> {
+    Client::builder()
+        .pool_max_idle_per_host(pool.max_idle_per_host)
+        .pool_idle_timeout(pool.idle_timeout)
+        .http2_only(version == UpstreamVersion::Http2)
+        .build(connector)
+}
+
+/// Upstream connection pool settings, shared by every connection of the proxy
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Idle connections kept open per upstream host
+    pub max_idle_per_host: usize,
+    /// Idle connections are closed after this delay, never with `None`
+    pub idle_timeout: Option<Duration>,
+    /// HTTP version of requests whose state has no `UpstreamVersion`
+    pub version: UpstreamVersion,
+    /// TCP keepalive probes interval on upstream connections, disabled with `None`
+    pub tcp_keepalive: Option<Duration>,
+    /// Disables Nagle's algorithm on upstream connections
+    pub tcp_nodelay: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_idle_per_host: usize::MAX,
+            idle_timeout: Some(Duration::from_secs(90)),
+            version: UpstreamVersion::Http1,
+            tcp_keepalive: None,
+            tcp_nodelay: false,
+        }
+    }
+}
+
+/// What sets clients apart besides the HTTP version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ClientKey {
+    version: UpstreamVersion,
+    connect_timeout: Option<Duration>,
+    // Address of the TLS config, its client is only used while the config is alive
+    // since a new config can reuse the address
+    #[cfg(feature = "https")]
+    tls: Option<usize>,
+}
+
+impl ClientKey {
+    /// Served by the clients created upfront with `connect_timeout`
+    fn is_default(&self, connect_timeout: Option<Duration>) -> bool {
+        #[cfg(feature = "https")]
+        {
+            if self.tls.is_some() {
+                return false;
+            }
+        }
+        self.connect_timeout == connect_timeout
+    }
+}
+
+struct CachedClient {
+    // Not kept alive by the cache, routes own their TLS config and drop it on reload
+    #[cfg(feature = "https")]
+    tls: Option<Weak<ClientConfig>>,
+    client: Client<Connector>,
+}
+
+impl CachedClient {
+    /// Whether the TLS config of this client can still be used by requests
+    fn is_alive(&self) -> bool {
+        #[cfg(feature = "https")]
+        {
+            if let Some(tls) = &self.tls {
+                return tls.strong_count() > 0;
+            }
+        }
+        true
+    }
+}
+
+/// Clients used to reach upstreams, one per HTTP version, connect timeout and TLS configuration.
+///
+/// Connections to upstreams are pooled by these clients, share them between services
+/// so upstream connections are reused across client connections.
+pub struct Clients {
+    pool: PoolConfig,
+    // Of the clients created upfront, other connect timeouts get cached clients
+    connect_timeout: Option<Duration>,
+    http1: Client<Connector>,
+    http2: Client<Connector>,
+    others: Mutex<HashMap<ClientKey, CachedClient>>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Clients::with_pool(PoolConfig::default())
+    }
+
+    pub fn with_pool(pool: PoolConfig) -> Self {
+        Clients::with_timeouts(pool, &UpstreamTimeouts::default())
+    }
+
+    /// Clients connecting within the connect timeout of `timeouts`, the proxy-wide timeouts,
+    /// only requests overriding it use clients of their own
+    pub fn with_timeouts(pool: PoolConfig, timeouts: &UpstreamTimeouts) -> Self {
+        let connect_timeout = timeouts.connect();
+        Clients {
+            http1: build_client(
+                &pool,
+                default_connector(&pool, UpstreamVersion::Http1, connect_timeout),
+                UpstreamVersion::Http1,
+            ),
+            http2: build_client(
+                &pool,
+                default_connector(&pool, UpstreamVersion::Http2, connect_timeout),
+                UpstreamVersion::Http2,
+            ),
+            others: Mutex::new(HashMap::new()),
+            connect_timeout,
+            pool,
+        }
+    }
+
+    /// Client matching the `UpstreamVersion`, `UpstreamTimeouts` and `UpstreamTls`
+    /// found in the request state
+    pub fn for_request(&self, state: &State) -> Client<Connector> {
+        let version = state
+            .get::<UpstreamVersion>()
+            .copied()
+            .unwrap_or(self.pool.version);
+        let connect_timeout = state
+            .get::<UpstreamTimeouts>()
+            .and_then(UpstreamTimeouts::connect);
+        #[cfg(feature = "https")]
+        let tls = state.get::<UpstreamTls>();
+
+        let key = ClientKey {
+            version,
+            connect_timeout,
+            #[cfg(feature = "https")]
+            tls: tls.map(UpstreamTls::key),
+        };
+        if key.is_default(self.connect_timeout) {
+            return match version {
+                UpstreamVersion::Http1 => self.http1.clone(),
+                UpstreamVersion::Http2 => self.http2.clone(),
+            };
+        }
+
+        let mut others = self.others.lock().unwrap();
+        if let Some(cached) = others.get(&key).filter(|cached| cached.is_alive()) {
+            return cached.client.clone();
+        }
+
+        // Clients of TLS configs dropped since, e.g. by a route reload, are evicted
+        others.retain(|_, cached| cached.is_alive());
+        let cached = self.build(
+            version,
+            connect_timeout,
+            #[cfg(feature = "https")]
+            tls,
+        );
+        let client = cached.client.clone();
+        others.insert(key, cached);
+        client
+    }
+
+    /// Clients created besides the default ones, for other connect timeouts and TLS configs
+    pub fn cached_clients(&self) -> usize {
+        self.others.lock().unwrap().len()
+    }
+
+    fn build(
+        &self,
+        version: UpstreamVersion,
+        connect_timeout: Option<Duration>,
+        #[cfg(feature = "https")] tls: Option<&UpstreamTls>,
+    ) -> CachedClient {
+        #[cfg(feature = "https")]
+        {
+            if let Some(tls) = tls {
+                return CachedClient {
+                    tls: Some(tls.downgrade()),
+                    client: tls.client(&self.pool, version, connect_timeout),
+                };
+            }
+        }
+        CachedClient {
+            #[cfg(feature = "https")]
+            tls: None,
+            client: build_client(
+                &self.pool,
+                default_connector(&self.pool, version, connect_timeout),
+                version,
+            ),
+        }
+    }
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Clients::new()
+    }
+}
@@ -1,5 +1,6 @@
 use hyper::{Body, Response, StatusCode};
 use std::error::Error;
+use std::fmt;
 
 #[derive(Debug)]
 pub struct MiddlewareError {
@@ -52,3 +53,64 @@ where
         MiddlewareError::new(err.to_string(), None, StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+/// Upstream request that did not complete, given to `request_failure` hooks
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// The upstream could not be reached, or the exchange with it failed
+    Http(hyper::Error),
+    /// The upstream took too long, answered 504 to the client
+    Timeout(Timeout),
+}
+
+/// Which upstream timeout elapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    Connect,
+    ResponseHeader,
+    Total,
+}
+
+impl UpstreamError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, UpstreamError::Timeout(_))
+    }
+}
+
+impl From<hyper::Error> for UpstreamError {
+    fn from(err: hyper::Error) -> UpstreamError {
+        // The connector reports its connect timeout as a `TimedOut` IO error
+        let mut source = err.source();
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                if err.is_connect() && io.kind() == std::io::ErrorKind::TimedOut {
+                    return UpstreamError::Timeout(Timeout::Connect);
+                }
+            }
+            source = cause.source();
+        }
+        UpstreamError::Http(err)
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpstreamError::Http(err) => write!(f, "{}", err),
+            UpstreamError::Timeout(Timeout::Connect) => write!(f, "upstream connect timeout"),
+            UpstreamError::Timeout(Timeout::ResponseHeader) => {
+                write!(f, "upstream response header timeout")
+            }
+            UpstreamError::Timeout(Timeout::Total) => write!(f, "upstream request timeout"),
+        }
+    }
+}
+
+impl Error for UpstreamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpstreamError::Http(err) => Some(err),
+            UpstreamError::Timeout(_) => None,
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use crate::proxy::error::MiddlewareError;
+use crate::proxy::error::{MiddlewareError, UpstreamError};
 use crate::proxy::service::{ServiceContext, State};
-use hyper::{Body, Error, Request, Response};
+use futures::future::{self, BoxFuture};
+use hyper::{Body, Request, Response};
+use std::sync::Mutex;
 
 pub enum MiddlewareResult {
     RespondWith(Response<hyper::Body>),
@@ -9,6 +11,10 @@ pub enum MiddlewareResult {
 
 use self::MiddlewareResult::Next;
 
+/// Middlewares are shared by every request of every connection,
+/// hooks only get a shared reference: use interior mutability (atomics, locks..) for mutable data.
+///
+/// See `MiddlewareMut` for middlewares needing exclusive access.
 pub trait Middleware {
     fn name() -> String
     where
@@ -21,48 +27,64 @@ pub trait Middleware {
         Self::name()
     }
 
-    fn set_state(&self, req_id: u64, state: &State, data: String) -> Result<(), MiddlewareError>
-    where
-        Self: Sized,
-    {
-        let mut state = state.lock()?;
-        state.insert((self.get_name(), req_id), data);
-        Ok(())
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(Next)
     }
 
-    fn state(req_id: u64, state: &State) -> Result<Option<String>, MiddlewareError>
-    where
-        Self: Sized,
-    {
-        let state = state.lock()?;
-        debug!("State length: {}", state.len());
-        let state = match state.get(&(Self::name(), req_id)) {
-            None => None,
-            Some(state) => Some(state.to_string()),
-        };
+    fn after_request(
+        &self,
+        _res: Option<&mut Response<Body>>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(Next)
+    }
 
-        debug!(
-            "[{}] State for {}: {:?}",
-            Self::name(),
-            &req_id.to_string()[..6],
-            state
-        );
+    fn request_failure(
+        &self,
+        _err: &UpstreamError,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(Next)
+    }
 
-        Ok(state)
+    fn request_success(
+        &self,
+        _res: &mut Response<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(Next)
     }
+}
 
-    fn get_state(&self, req_id: u64, state: &State) -> Result<Option<String>, MiddlewareError>
+/// Middleware with `&mut self` hooks, as middlewares were written before being shared.
+///
+/// Wrap it in `Locked` to add it to the proxy: it then gets its own lock,
+/// only requests going through this middleware wait for each other.
+pub trait MiddlewareMut {
+    fn name() -> String
+    where
+        Self: Sized;
+
+    fn get_name(&self) -> String
     where
         Self: Sized,
     {
-        Self::state(req_id, state)
+        Self::name()
     }
 
     fn before_request(
         &mut self,
         _req: &mut Request<Body>,
         _ctx: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         Ok(Next)
     }
@@ -71,16 +93,16 @@ pub trait Middleware {
         &mut self,
         _res: Option<&mut Response<Body>>,
         _ctx: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         Ok(Next)
     }
 
     fn request_failure(
         &mut self,
-        _err: &Error,
+        _err: &UpstreamError,
         _ctx: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         Ok(Next)
     }
@@ -89,8 +111,155 @@ pub trait Middleware {
         &mut self,
         _res: &mut Response<Body>,
         _ctx: &ServiceContext,
-        _state: &State,
+        _state: &mut State,
     ) -> Result<MiddlewareResult, MiddlewareError> {
         Ok(Next)
     }
 }
+
+/// Adapts a `MiddlewareMut` into a `Middleware` by guarding it with its own mutex.
+pub struct Locked<M>(Mutex<M>);
+
+impl<M> Locked<M> {
+    pub fn new(middleware: M) -> Self {
+        Locked(Mutex::new(middleware))
+    }
+}
+
+impl<M> Middleware for Locked<M>
+where
+    M: MiddlewareMut,
+{
+    fn name() -> String {
+        M::name()
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.0.lock()?.before_request(req, ctx, state)
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.0.lock()?.after_request(res, ctx, state)
+    }
+
+    fn request_failure(
+        &self,
+        err: &UpstreamError,
+        ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.0.lock()?.request_failure(err, ctx, state)
+    }
+
+    fn request_success(
+        &self,
+        res: &mut Response<Body>,
+        ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.0.lock()?.request_success(res, ctx, state)
+    }
+}
+
+pub type MiddlewareFuture<'a> = BoxFuture<'a, Result<MiddlewareResult, MiddlewareError>>;
+
+/// Middleware whose hooks return futures, awaited in order by the `ProxyService`.
+///
+/// Every `Middleware` is also an `AsyncMiddleware`, its hooks resolving immediately.
+/// Implement this trait directly when a hook needs to await I/O (e.g. calling an auth service).
+pub trait AsyncMiddleware {
+    fn before_request<'a>(
+        &'a self,
+        _req: &'a mut Request<Body>,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ok(Next))
+    }
+
+    fn after_request<'a>(
+        &'a self,
+        _res: Option<&'a mut Response<Body>>,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ok(Next))
+    }
+
+    fn request_failure<'a>(
+        &'a self,
+        _err: &'a UpstreamError,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ok(Next))
+    }
+
+    fn request_success<'a>(
+        &'a self,
+        _res: &'a mut Response<Body>,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ok(Next))
+    }
+}
+
+impl<M> AsyncMiddleware for M
+where
+    M: Middleware + Sync,
+{
+    fn before_request<'a>(
+        &'a self,
+        req: &'a mut Request<Body>,
+        ctx: &'a ServiceContext,
+        state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ready(Middleware::before_request(
+            self, req, ctx, state,
+        )))
+    }
+
+    fn after_request<'a>(
+        &'a self,
+        res: Option<&'a mut Response<Body>>,
+        ctx: &'a ServiceContext,
+        state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ready(Middleware::after_request(
+            self, res, ctx, state,
+        )))
+    }
+
+    fn request_failure<'a>(
+        &'a self,
+        err: &'a UpstreamError,
+        ctx: &'a ServiceContext,
+        state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ready(Middleware::request_failure(
+            self, err, ctx, state,
+        )))
+    }
+
+    fn request_success<'a>(
+        &'a self,
+        res: &'a mut Response<Body>,
+        ctx: &'a ServiceContext,
+        state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(future::ready(Middleware::request_success(
+            self, res, ctx, state,
+        )))
+    }
+}
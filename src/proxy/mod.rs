@@ -1,3 +1,7 @@
+pub mod cidr;
+pub mod circuit_breaker;
+pub mod client;
 pub mod error;
 pub mod middleware;
+pub mod retry;
 pub mod service;
@@ -0,0 +1,190 @@
+use hyper::body::{Bytes, HttpBody};
+use hyper::http::request::Parts;
+use hyper::{Body, Method, Request, Response};
+
+use crate::proxy::error::{Timeout, UpstreamError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How a failed upstream request is retried, insert it in the request `State` to enable retries.
+///
+/// Retries go to the same upstream, after an exponential backoff.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "router", derive(Deserialize), serde(default))]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: u32,
+    /// Retry when the upstream cannot be reached, or when the connection broke for idempotent requests
+    pub on_connection_error: bool,
+    /// Retry when the upstream answers one of these statuses
+    pub on_statuses: Vec<u16>,
+    /// Retry requests with a non idempotent method too, e.g. `POST`
+    pub non_idempotent: bool,
+    /// Requests with a larger body, or a body of unknown size, are not retried
+    pub max_body_bytes: u64,
+    /// Delay before the first retry, doubled for each following one
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            on_connection_error: true,
+            on_statuses: vec![502, 503, 504],
+            non_idempotent: false,
+            max_body_bytes: 64 * 1024,
+            backoff_ms: 25,
+            max_backoff_ms: 1000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether the request can be replayed, its body is then buffered
+    pub(crate) fn allows(&self, req: &Request<Body>) -> bool {
+        self.max_attempts > 1
+            && (self.non_idempotent || is_idempotent(req.method()))
+            && req
+                .body()
+                .size_hint()
+                .upper()
+                .is_some_and(|size| size <= self.max_body_bytes)
+    }
+
+    pub(crate) fn should_retry(
+        &self,
+        method: &Method,
+        result: &Result<Response<Body>, UpstreamError>,
+    ) -> bool {
+        match result {
+            Ok(res) => self.on_statuses.contains(&res.status().as_u16()),
+            Err(UpstreamError::Http(err)) => {
+                self.on_connection_error && (err.is_connect() || is_idempotent(method))
+            }
+            Err(UpstreamError::Timeout(Timeout::Connect)) => self.on_connection_error,
+            Err(UpstreamError::Timeout(Timeout::ResponseHeader)) => {
+                self.on_connection_error && is_idempotent(method)
+            }
+            Err(UpstreamError::Timeout(Timeout::Total)) => false,
+        }
+    }
+
+    /// Delay before the `retry`th retry, starting at 1
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u64.checked_shl(retry - 1).unwrap_or(u64::MAX);
+        Duration::from_millis(
+            self.backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Request buffered to be sent several times
+pub(crate) struct Replayable {
+    parts: Parts,
+    body: Bytes,
+}
+
+impl Replayable {
+    pub(crate) async fn new(req: Request<Body>) -> Result<Self, hyper::Error> {
+        let (parts, body) = req.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok(Replayable { parts, body })
+    }
+
+    pub(crate) fn method(&self) -> &Method {
+        &self.parts.method
+    }
+
+    pub(crate) fn request(&self) -> Request<Body> {
+        let mut req = Request::new(Body::from(self.body.clone()));
+        *req.method_mut() = self.parts.method.clone();
+        *req.uri_mut() = self.parts.uri.clone();
+        *req.version_mut() = self.parts.version;
+        *req.headers_mut() = self.parts.headers.clone();
+        req
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryBudgetConfig {
+    /// Retries earned by each request, `0.2` allows 20% more upstream requests at most
+    pub ratio: f64,
+    /// Retries that can be made at once, e.g. after an idle period
+    pub max_retries: f64,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        RetryBudgetConfig {
+            ratio: 0.2,
+            max_retries: 10.0,
+        }
+    }
+}
+
+/// Budget units making a retry, so fractions of a retry are counted without floats
+const RETRY: u64 = 1000;
+
+fn units(retries: f64) -> u64 {
+    (retries.max(0.0) * RETRY as f64).round() as u64
+}
+
+/// Retries shared by all the connections of the proxy, so retries cannot amplify an outage
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: u64,
+    max: u64,
+    /// In `RETRY` units, updated without locking as every request deposits
+    available: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        let max = units(config.max_retries);
+        RetryBudget {
+            ratio: units(config.ratio),
+            max,
+            available: AtomicU64::new(max),
+        }
+    }
+
+    /// Earns retries for a request sent upstream
+    pub(crate) fn deposit(&self) {
+        let _ = self
+            .available
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |available| {
+                // A full budget is left untouched
+                if available < self.max {
+                    Some((available + self.ratio).min(self.max))
+                } else {
+                    None
+                }
+            });
+    }
+
+    /// Spends a retry, `false` when the budget is exhausted
+    pub(crate) fn withdraw(&self) -> bool {
+        self.available
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |available| {
+                available.checked_sub(RETRY)
+            })
+            .is_ok()
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        RetryBudget::new(RetryBudgetConfig::default())
+    }
+}
@@ -1,39 +1,98 @@
 use futures::future;
-use futures::future::TryFutureExt;
-use hyper::client::connect::HttpConnector;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderName, HeaderValue, CONNECTION, UPGRADE};
 use hyper::service::Service;
-use hyper::{Body, Client, Request, Response};
+use hyper::upgrade::OnUpgrade;
+use hyper::{Body, Request, Response, StatusCode};
 use std::future::Future;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-use std::collections::HashMap;
+use std::any::Any;
 use std::net::SocketAddr;
 use std::{
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use rand::prelude::*;
 use rand::rngs::SmallRng;
 
+use crate::proxy::circuit_breaker::{CircuitBreakers, CircuitState};
+use crate::proxy::client::{Clients, Connector, UpstreamTimeouts, UpstreamVersion};
+use crate::proxy::error::{MiddlewareError, Timeout, UpstreamError};
 use crate::proxy::middleware::MiddlewareResult::*;
+use crate::proxy::retry::{Replayable, RetryBudget, RetryPolicy};
 use crate::Middlewares;
 
 // type BoxFut = Box<dyn Future<Output = Result<hyper::Response<Body>, hyper::Error>> + Send>;
-pub type State = Arc<Mutex<HashMap<(String, u64), String>>>;
+
+/// Typed storage living as long as a single request, shared by its middleware hooks.
+///
+/// Values are keyed by their type, middlewares should wrap their data in a dedicated type
+/// (e.g. `struct StartTime(DateTime<Utc>)`) so they do not overwrite each other.
+pub type State = http::Extensions;
+
+/// Header carrying the request ID unless another one is set
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from clients, longer ones are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Bridges an upgraded client connection to its upstream until one of them closes
+pub(crate) type Splice = future::BoxFuture<'static, ()>;
+
+/// Values kept until the exchange with the upstream is over: the response body is sent,
+/// or the upgraded connection closed. Dropped right away when there is no upstream response.
+///
+/// `before_request` hooks add them with `ExchangeGuards::hold`, e.g. the `Router`
+/// counts a request as in flight on its backend until then.
+#[derive(Default)]
+pub struct ExchangeGuards(Vec<Box<dyn Any + Send + Sync>>);
+
+impl ExchangeGuards {
+    /// Keeps `value` in the guards of the request owning `state`
+    pub fn hold<T: Any + Send + Sync>(state: &mut State, value: T) {
+        match state.get_mut::<ExchangeGuards>() {
+            Some(guards) => guards.0.push(Box::new(value)),
+            None => {
+                state.insert(ExchangeGuards(vec![Box::new(value)]));
+            }
+        }
+    }
+}
+
+/// Circuit breakers and the upstream host of a request
+type Circuit = Option<(Arc<CircuitBreakers>, String)>;
 
 pub struct ProxyService {
-    client: Client<HttpConnector>,
+    clients: Arc<Clients>,
     middlewares: Middlewares,
-    state: State,
+    circuit_breakers: Option<Arc<CircuitBreakers>>,
+    retry_budget: Arc<RetryBudget>,
+    timeouts: UpstreamTimeouts,
     remote_addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    tls: bool,
+    request_id_header: HeaderName,
+    upgrades: Option<mpsc::UnboundedSender<Splice>>,
     rng: SmallRng,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct ServiceContext {
     pub remote_addr: SocketAddr,
-    pub req_id: u64,
+    /// Address of the listener the request came through, `None` on Unix sockets
+    pub local_addr: Option<SocketAddr>,
+    /// Whether the client connection is served over TLS
+    pub tls: bool,
+    /// Taken from the request ID header, or a generated UUID when the client sent none.
+    /// It is forwarded upstream and echoed in the response.
+    pub req_id: String,
+    /// Circuit of the upstream host, known once `before_request` hooks ran
+    /// and only when circuit breakers are enabled
+    pub circuit: Option<CircuitState>,
 }
 
 impl Service<Request<hyper::Body>> for ProxyService {
@@ -41,114 +100,399 @@ impl Service<Request<hyper::Body>> for ProxyService {
     type Error = hyper::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        match self.client.poll_ready(cx) {
-            Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
-            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
-            Poll::Pending => Poll::Pending,
-        }
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Clients are always ready, waiting for a pooled connection happens in `request`
+        Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<hyper::Body>) -> Self::Future {
-        self.clear_state();
-        let (parts, body) = req.into_parts();
-        let mut req = Request::from_parts(parts, body);
-
-        // Create references for future callbacks
-        // references are moved in each chained future (map,then..)
-        let mws_failure = Arc::clone(&self.middlewares);
-        let mws_success = Arc::clone(&self.middlewares);
-        let mws_after_success = Arc::clone(&self.middlewares);
-        let mws_after_failure = Arc::clone(&self.middlewares);
-        let state_failure = Arc::clone(&self.state);
-        let state_success = Arc::clone(&self.state);
-        let state_after_success = Arc::clone(&self.state);
-        let state_after_failure = Arc::clone(&self.state);
+    fn call(&mut self, mut req: Request<hyper::Body>) -> Self::Future {
+        // Everything the request future needs is moved into it,
+        // middleware hooks are then awaited one after the other
+        let clients = Arc::clone(&self.clients);
+        let middlewares = Arc::clone(&self.middlewares);
+        let circuit_breakers = self.circuit_breakers.clone();
+        let retry_budget = Arc::clone(&self.retry_budget);
+        let default_timeouts = self.timeouts;
+        let upgrades = self.upgrades.clone();
 
-        let req_id = self.rng.next_u64();
+        let request_id_header = self.request_id_header.clone();
+        let req_id = match req.headers().get(&request_id_header) {
+            Some(value) if is_valid_request_id(value) => value.to_str().unwrap().to_string(),
+            _ => generate_request_id(&mut self.rng),
+        };
+        // Generated IDs and accepted ones are valid header values
+        req.headers_mut().insert(
+            request_id_header.clone(),
+            HeaderValue::from_str(&req_id).unwrap(),
+        );
 
-        let context = ServiceContext {
+        let mut context = ServiceContext {
             req_id,
             remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            tls: self.tls,
+            circuit: None,
         };
 
-        let mut before_res: Option<Response<Body>> = None;
-        for mw in self.middlewares.lock().unwrap().iter_mut() {
-            // Run all middlewares->before_request
-            if let Some(res) = match mw.before_request(&mut req, &context, &self.state) {
-                Err(err) => Some(Response::from(err)),
-                Ok(RespondWith(response)) => Some(response),
-                Ok(Next) => None,
-            } {
-                // Stop when an early response is wanted
-                before_res = Some(res);
-                break;
+        Box::pin(async move {
+            // Owned by this request only, requests of the same connection can be in flight
+            // at the same time. Dropped with the future once the response is returned.
+            let mut state = State::new();
+
+            let mut before_res: Option<Response<Body>> = None;
+            for mw in middlewares.iter() {
+                // Run all middlewares->before_request
+                if let Some(res) = match mw.before_request(&mut req, &context, &mut state).await {
+                    Err(err) => Some(Response::from(err)),
+                    Ok(RespondWith(response)) => Some(response),
+                    Ok(Next) => None,
+                } {
+                    // Stop when an early response is wanted
+                    before_res = Some(res);
+                    break;
+                }
             }
-        }
 
-        if let Some(res) = before_res {
-            return Box::pin(future::ok(self.early_response(&context, res, &self.state)));
-        }
+            if let Some(res) = before_res {
+                return Ok(Self::early_response(
+                    &middlewares,
+                    &context,
+                    &request_id_header,
+                    res,
+                    &mut state,
+                )
+                .await);
+            }
 
-        let res = self
-            .client
-            .request(req)
-            .map_err(move |err| {
-                for mw in mws_failure.lock().unwrap().iter_mut() {
-                    // TODO: think about graceful handling
-                    if let Err(err) = mw.request_failure(&err, &context, &state_failure) {
-                        error!("Request_failure errored: {:?}", &err);
-                    }
+            // Requests to an upstream host with an open circuit fail fast
+            let circuit: Circuit = match (&circuit_breakers, req.uri().authority()) {
+                (Some(breakers), Some(authority)) => {
+                    Some((Arc::clone(breakers), authority.to_string()))
+                }
+                _ => None,
+            };
+            if let Some((breakers, upstream)) = &circuit {
+                context.circuit = Some(breakers.acquire(upstream));
+                if context.circuit == Some(CircuitState::Open) {
+                    let res = Response::from(MiddlewareError::new(
+                        format!("Circuit open for {}", upstream),
+                        Some(String::from("Service unavailable")),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    ));
+                    return Ok(Self::early_response(
+                        &middlewares,
+                        &context,
+                        &request_id_header,
+                        res,
+                        &mut state,
+                    )
+                    .await);
                 }
-                err
-            })
-            .map_ok(move |mut res| {
-                for mw in mws_success.lock().unwrap().iter_mut() {
-                    match mw.request_success(&mut res, &context, &state_success) {
-                        Err(err) => res = Response::from(err),
-                        Ok(RespondWith(response)) => res = response,
-                        Ok(Next) => (),
+            }
+
+            // The client connection is taken over once the upstream switched protocols
+            let client_upgrade = if is_upgrade_request(&req) {
+                state.insert(UpstreamVersion::Http1);
+                Some(hyper::upgrade::on(&mut req))
+            } else {
+                None
+            };
+
+            // Route timeouts complete the proxy ones, the connect timeout selects the client
+            let timeouts = state
+                .get::<UpstreamTimeouts>()
+                .map_or(default_timeouts, |timeouts| timeouts.or(&default_timeouts));
+            state.insert(timeouts);
+            let deadline = timeouts.total().map(|total| Instant::now() + total);
+
+            let client = clients.for_request(&state);
+            let retry = match state.get::<RetryPolicy>() {
+                Some(policy) if client_upgrade.is_none() && policy.allows(&req) => {
+                    Some(policy.clone())
+                }
+                _ => None,
+            };
+            retry_budget.deposit();
+            let exchange = async {
+                match retry {
+                    Some(policy) => {
+                        Self::send_with_retries(
+                            &client,
+                            req,
+                            &policy,
+                            &timeouts,
+                            &retry_budget,
+                            &circuit,
+                            &context,
+                        )
+                        .await
+                    }
+                    None => {
+                        let result = send(&client, req, &timeouts).await;
+                        record_outcome(&circuit, &result);
+                        result
                     }
                 }
-                res
-            })
-            .map_ok_or_else(
-                move |err| {
-                    let mut res = Err(err);
-                    for mw in mws_after_success.lock().unwrap().iter_mut() {
-                        match mw.after_request(None, &context, &state_after_success) {
-                            Err(err) => res = Ok(Response::from(err)),
-                            Ok(RespondWith(response)) => res = Ok(response),
-                            Ok(Next) => (),
+            };
+            let result = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, exchange)
+                    .await
+                    .unwrap_or_else(|_| {
+                        record_outcome(&circuit, &Err(UpstreamError::Timeout(Timeout::Total)));
+                        Err(UpstreamError::Timeout(Timeout::Total))
+                    }),
+                None => exchange.await,
+            };
+
+            let guards = state.remove::<ExchangeGuards>();
+
+            let mut res = match result {
+                Ok(mut res) => {
+                    if let Some(client_upgrade) = client_upgrade {
+                        if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+                            let upstream_upgrade = hyper::upgrade::on(&mut res);
+                            let splice =
+                                splice_upgrade(client_upgrade, upstream_upgrade, context.clone());
+                            let splice: Splice = Box::pin(async move {
+                                splice.await;
+                                drop(guards);
+                            });
+                            match upgrades {
+                                Some(upgrades) => {
+                                    if let Err(unsent) = upgrades.send(splice) {
+                                        tokio::spawn(unsent.0);
+                                    }
+                                }
+                                None => {
+                                    tokio::spawn(splice);
+                                }
+                            }
                         }
+                    } else if deadline.is_some() || guards.is_some() {
+                        let body = std::mem::replace(res.body_mut(), Body::empty());
+                        *res.body_mut() = forward_body(body, deadline, guards, context.clone());
                     }
-                    res
-                },
-                move |mut res| {
-                    for mw in mws_after_failure.lock().unwrap().iter_mut() {
-                        match mw.after_request(Some(&mut res), &context, &state_after_failure) {
+
+                    for mw in middlewares.iter() {
+                        match mw.request_success(&mut res, &context, &mut state).await {
                             Err(err) => res = Response::from(err),
                             Ok(RespondWith(response)) => res = response,
                             Ok(Next) => (),
                         }
                     }
                     Ok(res)
-                },
-            );
+                }
+                Err(err) => {
+                    for mw in middlewares.iter() {
+                        // TODO: think about graceful handling
+                        if let Err(err) = mw.request_failure(&err, &context, &mut state).await {
+                            error!("Request_failure errored: {:?}", &err);
+                        }
+                    }
+                    match err {
+                        UpstreamError::Http(err) => Err(err),
+                        UpstreamError::Timeout(_) => Ok(Response::from(MiddlewareError::new(
+                            err.to_string(),
+                            Some(String::from("Gateway timeout")),
+                            StatusCode::GATEWAY_TIMEOUT,
+                        ))),
+                    }
+                }
+            };
+            if let Ok(res) = res.as_mut() {
+                echo_request_id(res, &request_id_header, &context);
+            }
+
+            for mw in middlewares.iter() {
+                match mw
+                    .after_request(res.as_mut().ok(), &context, &mut state)
+                    .await
+                {
+                    Err(err) => res = Ok(Response::from(err)),
+                    Ok(RespondWith(response)) => res = Ok(response),
+                    Ok(Next) => (),
+                }
+            }
+
+            res
+        })
+    }
+}
+
+/// Sends the request, waiting for the response headers until the response header timeout
+async fn send(
+    client: &hyper::Client<Connector>,
+    req: Request<Body>,
+    timeouts: &UpstreamTimeouts,
+) -> Result<Response<Body>, UpstreamError> {
+    match timeouts.response_header() {
+        Some(timeout) => match tokio::time::timeout(timeout, client.request(req)).await {
+            Ok(result) => result.map_err(UpstreamError::from),
+            Err(_) => Err(UpstreamError::Timeout(Timeout::ResponseHeader)),
+        },
+        None => client.request(req).await.map_err(UpstreamError::from),
+    }
+}
+
+/// Streams `body`, keeping `guards` until it ends. Past `deadline`, the body is aborted
+/// so the client sees an incomplete response.
+fn forward_body(
+    mut body: Body,
+    deadline: Option<Instant>,
+    guards: Option<ExchangeGuards>,
+    context: ServiceContext,
+) -> Body {
+    let (mut sender, streamed) = Body::channel();
+    tokio::spawn(async move {
+        let forward = async {
+            while let Some(chunk) = body.data().await {
+                sender.send_data(chunk?).await?;
+            }
+            if let Some(trailers) = body.trailers().await? {
+                sender.send_trailers(trailers).await?;
+            }
+            Ok::<_, hyper::Error>(())
+        };
+        let result = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, forward).await,
+            None => Ok(forward.await),
+        };
+        drop(guards);
+        match result {
+            Ok(Ok(())) => (),
+            Ok(Err(_)) => sender.abort(),
+            Err(_) => {
+                debug!(
+                    "[{}] {}, aborting the response body",
+                    context.req_id,
+                    UpstreamError::Timeout(Timeout::Total)
+                );
+                sender.abort();
+            }
+        }
+    });
+    streamed
+}
 
-        Box::pin(res)
+/// Client IDs are kept when short and printable, so they can be logged and forwarded as is
+fn is_valid_request_id(value: &HeaderValue) -> bool {
+    let bytes = value.as_bytes();
+    !bytes.is_empty()
+        && bytes.len() <= MAX_REQUEST_ID_LENGTH
+        && bytes.iter().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Random (version 4) UUID, e.g. `5f0c6f5e-8f3a-4b8e-9d7a-1c2b3d4e5f60`
+fn generate_request_id(rng: &mut SmallRng) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Sets the request ID on the response, before `after_request` hooks so they can still change it
+fn echo_request_id(res: &mut Response<Body>, header: &HeaderName, context: &ServiceContext) {
+    if let Ok(value) = HeaderValue::from_str(&context.req_id) {
+        res.headers_mut().insert(header.clone(), value);
+    }
+}
+
+/// Feeds the circuit breaker of the upstream with the result of a request sent to it
+fn record_outcome(circuit: &Circuit, result: &Result<Response<Body>, UpstreamError>) {
+    if let Some((breakers, upstream)) = circuit {
+        let success = match result {
+            Ok(res) => !res.status().is_server_error(),
+            Err(_) => false,
+        };
+        breakers.record(upstream, success);
+    }
+}
+
+/// HTTP/1.1 `Connection: Upgrade` requests, e.g. WebSocket handshakes
+pub(crate) fn is_upgrade_request(req: &Request<Body>) -> bool {
+    req.headers().contains_key(UPGRADE)
+        && req
+            .headers()
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Copies bytes both ways between the upgraded client and upstream connections until one closes
+async fn splice_upgrade(client: OnUpgrade, upstream: OnUpgrade, context: ServiceContext) {
+    match future::try_join(client, upstream).await {
+        Ok((mut client, mut upstream)) => {
+            match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                Ok((sent, received)) => debug!(
+                    "[{}] Upgraded connection closed, {} bytes sent, {} bytes received",
+                    context.req_id, sent, received
+                ),
+                Err(err) => debug!("[{}] Upgraded connection error: {}", context.req_id, err),
+            }
+        }
+        Err(err) => error!("[{}] Cannot upgrade connection: {}", context.req_id, err),
     }
 }
 
 impl ProxyService {
-    fn early_response(
-        &self,
+    async fn send_with_retries(
+        client: &hyper::Client<Connector>,
+        req: Request<Body>,
+        policy: &RetryPolicy,
+        timeouts: &UpstreamTimeouts,
+        budget: &RetryBudget,
+        circuit: &Circuit,
         context: &ServiceContext,
+    ) -> Result<Response<Body>, UpstreamError> {
+        let req = Replayable::new(req).await?;
+        let mut attempt = 1;
+        loop {
+            let result = send(client, req.request(), timeouts).await;
+            record_outcome(circuit, &result);
+
+            if attempt >= policy.max_attempts || !policy.should_retry(req.method(), &result) {
+                return result;
+            }
+            if let Some((breakers, upstream)) = circuit {
+                if breakers.state(upstream) == CircuitState::Open {
+                    return result;
+                }
+            }
+            if !budget.withdraw() {
+                debug!("[{}] Retry budget exhausted, not retrying", context.req_id);
+                return result;
+            }
+
+            tokio::time::sleep(policy.backoff(attempt)).await;
+            attempt += 1;
+            debug!(
+                "[{}] Retrying upstream request, attempt {}",
+                context.req_id, attempt
+            );
+        }
+    }
+
+    async fn early_response(
+        middlewares: &Middlewares,
+        context: &ServiceContext,
+        request_id_header: &HeaderName,
         mut res: Response<Body>,
-        state: &State,
+        state: &mut State,
     ) -> Response<Body> {
-        for mw in self.middlewares.lock().unwrap().iter_mut() {
-            match mw.after_request(Some(&mut res), context, state) {
+        echo_request_id(&mut res, request_id_header, context);
+        for mw in middlewares.iter() {
+            match mw.after_request(Some(&mut res), context, state).await {
                 Err(err) => res = Response::from(err),
                 Ok(RespondWith(response)) => res = response,
                 Ok(Next) => (),
@@ -158,24 +502,67 @@ impl ProxyService {
         res
     }
 
-    // Needed to avoid a single connection creating too much data in state
-    // Since we need to identify each request in state (HashMap tuple identifier), it grows
-    // for each request from the same connection
-    fn clear_state(&self) {
-        if let Ok(mut state) = self.state.lock() {
-            state.clear();
-        } else {
-            error!("[FATAL] Cannot lock state in clean_stale_state");
-        }
+    /// Creates a service reaching upstreams with clients of its own, see `with_clients`
+    pub fn new(middlewares: Middlewares, remote_addr: SocketAddr) -> Self {
+        ProxyService::with_clients(middlewares, remote_addr, Arc::new(Clients::new()))
     }
 
-    pub fn new(middlewares: Middlewares, remote_addr: SocketAddr) -> Self {
+    /// Creates a service reaching upstreams with `clients`,
+    /// shared with other services to share their connection pools
+    pub fn with_clients(
+        middlewares: Middlewares,
+        remote_addr: SocketAddr,
+        clients: Arc<Clients>,
+    ) -> Self {
         ProxyService {
-            client: Client::new(),
-            state: Arc::new(Mutex::new(HashMap::new())),
+            clients,
+            circuit_breakers: None,
+            retry_budget: Arc::new(RetryBudget::default()),
+            timeouts: UpstreamTimeouts::default(),
             rng: SmallRng::from_entropy(),
             remote_addr,
+            local_addr: None,
+            tls: false,
+            request_id_header: REQUEST_ID,
+            upgrades: None,
             middlewares,
         }
     }
+
+    /// Address the connection was accepted on, given to middlewares in the `ServiceContext`
+    pub fn set_local_addr(&mut self, local_addr: SocketAddr) {
+        self.local_addr = Some(local_addr);
+    }
+
+    /// Header the request ID is read from, forwarded upstream and echoed in, `X-Request-Id` by default
+    pub fn set_request_id_header(&mut self, header: HeaderName) {
+        self.request_id_header = header;
+    }
+
+    /// Hands upgraded connections over to the connection task instead of spawning them,
+    /// so they are drained along with it on shutdown
+    pub(crate) fn set_upgrades(&mut self, upgrades: mpsc::UnboundedSender<Splice>) {
+        self.upgrades = Some(upgrades);
+    }
+
+    /// Tells middlewares the connection is served over TLS, through the `ServiceContext`
+    pub fn set_tls(&mut self, tls: bool) {
+        self.tls = tls;
+    }
+
+    /// Tracks upstream failures in `circuit_breakers`, shared with other services
+    pub fn set_circuit_breakers(&mut self, circuit_breakers: Arc<CircuitBreakers>) {
+        self.circuit_breakers = Some(circuit_breakers);
+    }
+
+    /// Spends retries from `retry_budget`, shared with other services.
+    /// Each service has a budget of its own otherwise.
+    pub fn set_retry_budget(&mut self, retry_budget: Arc<RetryBudget>) {
+        self.retry_budget = retry_budget;
+    }
+
+    /// Timeouts of requests whose state has no `UpstreamTimeouts`, or for the fields it leaves out
+    pub fn set_timeouts(&mut self, timeouts: UpstreamTimeouts) {
+        self.timeouts = timeouts;
+    }
 }
@@ -0,0 +1,171 @@
+//! PROXY protocol v1 and v2 headers, sent by load balancers ahead of the proxied connection
+//! to tell the client address. See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header, `\r\n` included
+const V1_MAX_LENGTH: usize = 107;
+
+/// Addresses of the original connection, unknown for health checks of the load balancer
+/// (`LOCAL` and `UNKNOWN` headers) and for address families other than TCP/UDP over IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+#[derive(Debug)]
+pub enum ProxyHeaderError {
+    Io(io::Error),
+    /// The connection does not start with a PROXY protocol signature
+    MissingHeader,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ProxyHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProxyHeaderError::Io(err) => write!(f, "cannot read PROXY protocol header: {}", err),
+            ProxyHeaderError::MissingHeader => write!(f, "missing PROXY protocol header"),
+            ProxyHeaderError::Invalid(reason) => {
+                write!(f, "invalid PROXY protocol header: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProxyHeaderError {}
+
+impl From<io::Error> for ProxyHeaderError {
+    fn from(err: io::Error) -> Self {
+        ProxyHeaderError::Io(err)
+    }
+}
+
+/// Reads a v1 or v2 header from the start of `io`, the bytes following it are left unread
+pub async fn read_header<R>(io: &mut R) -> Result<ProxyHeader, ProxyHeaderError>
+where
+    R: AsyncRead + Unpin,
+{
+    // Shorter than the shortest v1 header, `PROXY UNKNOWN\r\n`
+    let mut start = [0; 12];
+    io.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(io).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(io, &start).await
+    } else {
+        Err(ProxyHeaderError::MissingHeader)
+    }
+}
+
+async fn read_v1<R>(io: &mut R, start: &[u8]) -> Result<ProxyHeader, ProxyHeaderError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(ProxyHeaderError::Invalid("v1 header too long"));
+        }
+        line.push(io.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| ProxyHeaderError::Invalid("v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let address = |ip: &str, port: &str| -> Result<SocketAddr, ProxyHeaderError> {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| ProxyHeaderError::Invalid("bad v1 address"))?;
+                if ip.is_ipv4() != (*protocol == "TCP4") {
+                    return Err(ProxyHeaderError::Invalid("v1 address of the wrong family"));
+                }
+                let port = port
+                    .parse()
+                    .map_err(|_| ProxyHeaderError::Invalid("bad v1 port"))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Ok(ProxyHeader {
+                source: Some(address(source, source_port)?),
+                destination: Some(address(destination, destination_port)?),
+            })
+        }
+        _ => Err(ProxyHeaderError::Invalid("malformed v1 header")),
+    }
+}
+
+async fn read_v2<R>(io: &mut R) -> Result<ProxyHeader, ProxyHeaderError>
+where
+    R: AsyncRead + Unpin,
+{
+    let version_command = io.read_u8().await?;
+    let family = io.read_u8().await?;
+    let length = usize::from(io.read_u16().await?);
+    let mut payload = vec![0; length];
+    io.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyHeaderError::Invalid("unsupported version"));
+    }
+    let unknown = ProxyHeader {
+        source: None,
+        destination: None,
+    };
+    match version_command & 0x0f {
+        // LOCAL, sent by the load balancer itself
+        0 => return Ok(unknown),
+        1 => (),
+        _ => return Err(ProxyHeaderError::Invalid("unsupported command")),
+    }
+
+    // The high nibble is the address family, the low one the transport (TCP or UDP)
+    let (source, destination) = match family >> 4 {
+        1 => {
+            if payload.len() < 12 {
+                return Err(ProxyHeaderError::Invalid("truncated IPv4 addresses"));
+            }
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    payload[at],
+                    payload[at + 1],
+                    payload[at + 2],
+                    payload[at + 3],
+                ))
+            };
+            (ip(0), ip(4))
+        }
+        2 => {
+            if payload.len() < 36 {
+                return Err(ProxyHeaderError::Invalid("truncated IPv6 addresses"));
+            }
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&payload[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            (ip(0), ip(16))
+        }
+        // Unspecified or Unix sockets
+        _ => return Ok(unknown),
+    };
+    let ports = if source.is_ipv4() { 8 } else { 32 };
+    let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+
+    Ok(ProxyHeader {
+        source: Some(SocketAddr::new(source, port(ports))),
+        destination: Some(SocketAddr::new(destination, port(ports + 2))),
+    })
+}
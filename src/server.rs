@@ -0,0 +1,333 @@
+use futures::future;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hyper::header::HeaderName;
+use hyper::server::conn::Http;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+#[cfg(unix)]
+use {
+    std::os::unix::fs::FileTypeExt,
+    std::path::PathBuf,
+    tokio::net::{UnixListener, UnixStream},
+};
+
+use crate::listener::ListenAddr;
+use crate::proxy::circuit_breaker::CircuitBreakers;
+use crate::proxy::client::{Clients, UpstreamTimeouts};
+use crate::proxy::retry::RetryBudget;
+use crate::proxy::service::{ProxyService, Splice};
+use crate::proxy_protocol;
+use crate::Middlewares;
+
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Address given to Unix domain socket peers
+#[cfg(unix)]
+const UNIX_PEER_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 0);
+
+/// Time given to load balancers to send the PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl BoundListener {
+    pub(crate) async fn bind(addr: &ListenAddr) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(BoundListener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                Ok(BoundListener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+        }
+    }
+
+    /// Accepts the next connection, retrying on errors such as too many open files
+    async fn accept(&self) -> Accepted {
+        loop {
+            let accepted = match self {
+                BoundListener::Tcp(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, addr)| Accepted::Tcp(stream, addr)),
+                #[cfg(unix)]
+                BoundListener::Unix(listener, _) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| Accepted::Unix(stream)),
+            };
+            match accepted {
+                Ok(accepted) => return accepted,
+                Err(err) => {
+                    error!("Cannot accept connection: {}", err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for BoundListener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if let BoundListener::Unix(_, path) = self {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Open connections, drained on shutdown
+struct Connections {
+    tasks: JoinSet<()>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Connections {
+    fn new() -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Connections {
+            tasks: JoinSet::new(),
+            shutdown,
+        }
+    }
+
+    /// Resolves once shutdown has started, to be passed to `serve_connection`
+    fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    fn spawn<F>(&mut self, connection: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(connection);
+    }
+
+    /// Waits for a connection to close, never resolves when there is none
+    async fn reap(&mut self) {
+        if self.tasks.join_next().await.is_none() {
+            future::pending::<()>().await;
+        }
+    }
+
+    /// Asks every connection to finish its in-flight requests, idle ones are closed right away.
+    /// Connections still open after `timeout` are dropped.
+    async fn drain(mut self, timeout: Duration) {
+        let _ = self.shutdown.send(true);
+
+        if !self.tasks.is_empty() {
+            info!("Waiting for {} connection(s) to close", self.tasks.len());
+        }
+
+        let tasks = &mut self.tasks;
+        let closed = async move { while tasks.join_next().await.is_some() {} };
+        if tokio::time::timeout(timeout, closed).await.is_err() {
+            warn!(
+                "Shutdown timeout reached, dropping {} connection(s)",
+                self.tasks.len()
+            );
+            self.tasks.shutdown().await;
+        }
+    }
+}
+
+/// How the connections of a listener are served
+#[derive(Clone)]
+pub(crate) struct Handler {
+    pub(crate) middlewares: Middlewares,
+    pub(crate) clients: Arc<Clients>,
+    pub(crate) circuit_breakers: Option<Arc<CircuitBreakers>>,
+    pub(crate) retry_budget: Arc<RetryBudget>,
+    pub(crate) timeouts: UpstreamTimeouts,
+    pub(crate) request_id_header: HeaderName,
+    pub(crate) proxy_protocol: bool,
+    #[cfg(feature = "tls")]
+    pub(crate) acceptor: Option<TlsAcceptor>,
+}
+
+impl Handler {
+    fn spawn<I>(
+        &self,
+        connections: &mut Connections,
+        io: I,
+        remote_addr: SocketAddr,
+        local_addr: Option<SocketAddr>,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let shutdown_signal = connections.shutdown_signal();
+        connections.spawn(
+            self.clone()
+                .handle(io, remote_addr, local_addr, shutdown_signal),
+        );
+    }
+
+    /// Reads the PROXY protocol header if expected, then serves the connection
+    async fn handle<I>(
+        self,
+        mut io: I,
+        mut remote_addr: SocketAddr,
+        local_addr: Option<SocketAddr>,
+        shutdown_signal: watch::Receiver<bool>,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if self.proxy_protocol {
+            let header = proxy_protocol::read_header(&mut io);
+            match tokio::time::timeout(PROXY_HEADER_TIMEOUT, header).await {
+                Ok(Ok(header)) => {
+                    if let Some(source) = header.source {
+                        remote_addr = source;
+                    }
+                }
+                Ok(Err(err)) => {
+                    debug!("Closing connection from IP {}: {}", &remote_addr, err);
+                    return;
+                }
+                Err(_) => {
+                    debug!("No PROXY protocol header from IP {} in time", &remote_addr);
+                    return;
+                }
+            }
+        }
+
+        debug!("Handling connection for IP: {}", &remote_addr);
+
+        let mut service = ProxyService::with_clients(
+            Arc::clone(&self.middlewares),
+            remote_addr,
+            Arc::clone(&self.clients),
+        );
+        if let Some(local_addr) = local_addr {
+            service.set_local_addr(local_addr);
+        }
+        if let Some(circuit_breakers) = &self.circuit_breakers {
+            service.set_circuit_breakers(Arc::clone(circuit_breakers));
+        }
+        service.set_retry_budget(Arc::clone(&self.retry_budget));
+        service.set_timeouts(self.timeouts);
+        service.set_request_id_header(self.request_id_header.clone());
+
+        #[cfg(feature = "tls")]
+        {
+            if let Some(acceptor) = &self.acceptor {
+                service.set_tls(true);
+                match acceptor.accept(io).await {
+                    Ok(stream) => {
+                        let alpn_h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+                        serve_connection(stream, service, remote_addr, alpn_h2, shutdown_signal)
+                            .await
+                    }
+                    Err(err) => debug!("TLS handshake failed for IP {}: {}", &remote_addr, err),
+                }
+                return;
+            }
+        }
+
+        serve_connection(io, service, remote_addr, false, shutdown_signal).await
+    }
+}
+
+/// Accepts connections until `stop` changes, then drains them
+pub(crate) async fn serve_listener(
+    listener: BoundListener,
+    handler: Handler,
+    mut stop: watch::Receiver<bool>,
+    shutdown_timeout: Duration,
+) {
+    let mut connections = Connections::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Accepted::Tcp(stream, remote_addr) => {
+                    let local_addr = stream.local_addr().ok();
+                    handler.spawn(&mut connections, stream, remote_addr, local_addr)
+                }
+                #[cfg(unix)]
+                Accepted::Unix(stream) => handler.spawn(&mut connections, stream, UNIX_PEER_ADDR.into(), None),
+            },
+            _ = connections.reap() => (),
+            _ = stop.changed() => break,
+        }
+    }
+
+    drop(listener);
+    connections.drain(shutdown_timeout).await;
+}
+
+/// Serves the requests of a single connection until it is closed.
+///
+/// HTTP/2 is used when negotiated through ALPN (`h2`), otherwise HTTP/1 and prior knowledge HTTP/2 (h2c)
+/// are told apart from the first bytes of the connection.
+///
+/// Upgraded connections are bridged by this task too, so draining waits for them
+/// and drops them once the shutdown timeout elapsed.
+async fn serve_connection<I>(
+    io: I,
+    mut service: ProxyService,
+    remote_addr: SocketAddr,
+    alpn_h2: bool,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut http = Http::new();
+    if alpn_h2 {
+        http.http2_only(true);
+    }
+
+    let (upgrades, mut upgraded) = mpsc::unbounded_channel();
+    service.set_upgrades(upgrades);
+    let conn = http.serve_connection(io, service).with_upgrades();
+    tokio::pin!(conn);
+
+    let mut splices = FuturesUnordered::<Splice>::new();
+    let mut served = false;
+    let mut upgrades_done = false;
+    let mut shutting_down = false;
+    while !(served && upgrades_done && splices.is_empty()) {
+        tokio::select! {
+            res = conn.as_mut(), if !served => {
+                served = true;
+                // Responses are sent, no upgrade can come after the ones queued
+                upgraded.close();
+                if let Err(err) = res {
+                    debug!("Connection error for IP {}: {}", &remote_addr, err);
+                }
+            }
+            splice = upgraded.recv(), if !upgrades_done => match splice {
+                Some(splice) => splices.push(splice),
+                None => upgrades_done = true,
+            },
+            Some(()) = splices.next(), if !splices.is_empty() => (),
+            _ = shutdown.changed(), if !shutting_down && !served => {
+                shutting_down = true;
+                conn.as_mut().graceful_shutdown();
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+#[cfg(feature = "tls")]
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use rustls::{Certificate, PrivateKey};
+use rustls_pemfile::Item;
+#[cfg(feature = "tls")]
+use {
+    rustls::server::{ClientHello, ResolvesServerCert},
+    rustls::sign::{self, CertifiedKey},
+    rustls::ServerConfig,
+    tokio_rustls::TlsAcceptor,
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io { path: String, source: io::Error },
+    NoCertificate { path: String },
+    NoPrivateKey { path: String },
+    InvalidCertificate { path: String },
+    InvalidPrivateKey { path: String },
+    IncompleteClientAuth { path: String },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::Io { path, source } => write!(f, "cannot read {}: {}", path, source),
+            TlsError::NoCertificate { path } => write!(f, "no PEM certificate found in {}", path),
+            TlsError::NoPrivateKey { path } => write!(f, "no PEM private key found in {}", path),
+            TlsError::InvalidCertificate { path } => write!(f, "invalid certificate in {}", path),
+            TlsError::InvalidPrivateKey { path } => {
+                write!(f, "unsupported private key type in {}", path)
+            }
+            TlsError::IncompleteClientAuth { path } => write!(
+                f,
+                "{} is set alone, client_cert and client_key go together",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TlsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Certificates used to serve HTTPS.
+///
+/// The certificate is selected from the SNI hostname sent by the client,
+/// falling back to the default certificate when there is no match.
+#[cfg(feature = "tls")]
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    default: Option<Arc<CertifiedKey>>,
+    by_hostname: HashMap<String, Arc<CertifiedKey>>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    /// Creates a config without any certificate, see `add_certificate`
+    pub fn new() -> Self {
+        TlsConfig::default()
+    }
+
+    /// Creates a config serving the given certificate chain and private key for every hostname
+    pub fn from_pem_files(cert_path: &str, key_path: &str) -> Result<Self, TlsError> {
+        let mut config = TlsConfig::new();
+        config.set_default_certificate(cert_path, key_path)?;
+        Ok(config)
+    }
+
+    /// Certificate used when no certificate matches the SNI hostname, or when the client sent none
+    pub fn set_default_certificate(
+        &mut self,
+        cert_path: &str,
+        key_path: &str,
+    ) -> Result<(), TlsError> {
+        self.default = Some(load_certified_key(cert_path, key_path)?);
+        Ok(())
+    }
+
+    /// Certificate used when the client asks for `hostname` through SNI
+    pub fn add_certificate(
+        &mut self,
+        hostname: &str,
+        cert_path: &str,
+        key_path: &str,
+    ) -> Result<(), TlsError> {
+        let key = load_certified_key(cert_path, key_path)?;
+        self.by_hostname.insert(hostname.to_lowercase(), key);
+        Ok(())
+    }
+
+    pub(crate) fn server_config(&self) -> ServerConfig {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self.clone()));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::new(self.server_config()))
+    }
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for TlsConfig {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.by_hostname.get(&name.to_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| TlsError::Io {
+            path: path.to_string(),
+            source,
+        })
+}
+
+fn read_items(path: &str) -> Result<Vec<Item>, TlsError> {
+    rustls_pemfile::read_all(&mut open(path)?).map_err(|source| TlsError::Io {
+        path: path.to_string(),
+        source,
+    })
+}
+
+pub(crate) fn load_certificates(path: &str) -> Result<Vec<Certificate>, TlsError> {
+    let certs: Vec<Certificate> = read_items(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate {
+            path: path.to_string(),
+        });
+    }
+    Ok(certs)
+}
+
+pub(crate) fn load_private_key(path: &str) -> Result<PrivateKey, TlsError> {
+    read_items(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoPrivateKey {
+            path: path.to_string(),
+        })
+}
+
+#[cfg(feature = "tls")]
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>, TlsError> {
+    let certs = load_certificates(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let key = sign::any_supported_type(&key).map_err(|_| TlsError::InvalidPrivateKey {
+        path: key_path.to_string(),
+    })?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
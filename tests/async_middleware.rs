@@ -0,0 +1,118 @@
+mod common;
+
+use hyper::{Body, Request, Response};
+use simple_proxy::proxy::middleware::{AsyncMiddleware, MiddlewareFuture, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Calls = Arc<Mutex<Vec<String>>>;
+
+/// Records its hooks after awaiting a delay, `before_request` answers when `respond` is set
+struct Slow {
+    name: &'static str,
+    delay: Duration,
+    respond: bool,
+    calls: Calls,
+}
+
+impl Slow {
+    fn new(name: &'static str, delay_ms: u64, respond: bool, calls: &Calls) -> Self {
+        Slow {
+            name,
+            delay: Duration::from_millis(delay_ms),
+            respond,
+            calls: Arc::clone(calls),
+        }
+    }
+
+    async fn record(&self, hook: &str) {
+        tokio::time::sleep(self.delay).await;
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", self.name, hook));
+    }
+}
+
+impl AsyncMiddleware for Slow {
+    fn before_request<'a>(
+        &'a self,
+        _req: &'a mut Request<Body>,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(async move {
+            self.record("before_request").await;
+            if self.respond {
+                let res = Response::new(Body::from(self.name));
+                return Ok(MiddlewareResult::RespondWith(res));
+            }
+            Ok(MiddlewareResult::Next)
+        })
+    }
+
+    fn after_request<'a>(
+        &'a self,
+        _res: Option<&'a mut Response<Body>>,
+        _ctx: &'a ServiceContext,
+        _state: &'a mut State,
+    ) -> MiddlewareFuture<'a> {
+        Box::pin(async move {
+            self.record("after_request").await;
+            Ok(MiddlewareResult::Next)
+        })
+    }
+}
+
+async fn send(middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>>) -> String {
+    let mut service = common::service(middlewares);
+    let req = Request::get("/").body(Body::empty()).unwrap();
+    common::send(&mut service, req).await.1
+}
+
+#[tokio::test]
+async fn async_hooks_are_awaited_in_order() {
+    let calls = Calls::default();
+    // The slowest middleware comes first, its hooks still run before the others
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![
+        Box::new(Slow::new("first", 50, false, &calls)),
+        Box::new(Slow::new("second", 10, false, &calls)),
+        Box::new(Slow::new("third", 0, true, &calls)),
+    ];
+
+    assert_eq!(send(middlewares).await, "third");
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            "first before_request",
+            "second before_request",
+            "third before_request",
+            "first after_request",
+            "second after_request",
+            "third after_request",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn respond_with_from_an_async_hook_skips_the_next_ones() {
+    let calls = Calls::default();
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![
+        Box::new(Slow::new("first", 10, false, &calls)),
+        Box::new(Slow::new("second", 30, true, &calls)),
+        Box::new(Slow::new("third", 0, true, &calls)),
+    ];
+
+    assert_eq!(send(middlewares).await, "second");
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            "first before_request",
+            "second before_request",
+            "first after_request",
+            "second after_request",
+            "third after_request",
+        ]
+    );
+}
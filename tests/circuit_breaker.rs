@@ -0,0 +1,128 @@
+mod common;
+
+use common::Forward;
+use hyper::{Body, Request, Response, StatusCode};
+use simple_proxy::proxy::circuit_breaker::{CircuitBreakerConfig, CircuitBreakers};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Answers 500 while `failing`, counts the requests it received
+fn spawn_upstream(failing: Arc<AtomicBool>, hits: Arc<AtomicUsize>) -> SocketAddr {
+    common::spawn_upstream(move |_req| {
+        hits.fetch_add(1, Ordering::SeqCst);
+        let mut res = Response::new(Body::empty());
+        if failing.load(Ordering::SeqCst) {
+            *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        async move { res }
+    })
+}
+
+/// Echoes the circuit state in the `x-circuit` header
+struct CircuitHeader;
+
+impl Middleware for CircuitHeader {
+    fn name() -> String {
+        String::from("CircuitHeader")
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if let (Some(res), Some(circuit)) = (res, ctx.circuit) {
+            res.headers_mut()
+                .insert("x-circuit", format!("{:?}", circuit).parse()?);
+        }
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+fn service(upstream: SocketAddr) -> ProxyService {
+    let mut service = common::service(vec![Box::new(Forward(upstream)), Box::new(CircuitHeader)]);
+    service.set_circuit_breakers(Arc::new(CircuitBreakers::new(CircuitBreakerConfig {
+        failure_threshold: 2,
+        cool_down: Duration::from_millis(200),
+    })));
+    service
+}
+
+async fn send(service: &mut ProxyService) -> Result<(StatusCode, String), hyper::Error> {
+    let res = common::call(service, Request::get("/").body(Body::empty()).unwrap()).await?;
+    let circuit = res.headers()["x-circuit"].to_str().unwrap().to_string();
+    Ok((res.status(), circuit))
+}
+
+#[tokio::test]
+async fn opens_after_consecutive_errors_and_closes_after_a_successful_trial() {
+    let failing = Arc::new(AtomicBool::new(true));
+    let hits = Arc::new(AtomicUsize::new(0));
+    let upstream = spawn_upstream(Arc::clone(&failing), Arc::clone(&hits));
+    let mut service = service(upstream);
+
+    for _ in 0..2 {
+        let (status, circuit) = send(&mut service).await.unwrap();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(circuit, "Closed");
+    }
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(circuit, "Open");
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    failing.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(circuit, "HalfOpen");
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(circuit, "Closed");
+    assert_eq!(hits.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn failed_trial_opens_the_circuit_again() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let upstream = spawn_upstream(Arc::new(AtomicBool::new(true)), Arc::clone(&hits));
+    let mut service = service(upstream);
+
+    send(&mut service).await.unwrap();
+    send(&mut service).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(circuit, "HalfOpen");
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(circuit, "Open");
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn connection_errors_open_the_circuit() {
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut service = service(closed);
+
+    assert!(send(&mut service).await.is_err());
+    assert!(send(&mut service).await.is_err());
+
+    let (status, circuit) = send(&mut service).await.unwrap();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(circuit, "Open");
+}
@@ -0,0 +1,159 @@
+//! Fixtures shared by the integration tests, each test file only uses some of them
+#![allow(dead_code)]
+
+use futures::future::poll_fn;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Request, Response, Server, Uri};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Serves `handler` on a local port, as a stub upstream
+pub fn spawn_upstream<F, R>(handler: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    serve(local_listener(), false, None, handler)
+}
+
+/// Same as `spawn_upstream`, on a listener bound beforehand
+pub fn spawn_upstream_on<F, R>(listener: TcpListener, handler: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    serve(listener, false, None, handler)
+}
+
+/// Same as `spawn_upstream`, only speaking HTTP/2 (h2c)
+pub fn spawn_h2_upstream<F, R>(handler: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    serve(local_listener(), true, None, handler)
+}
+
+/// Same as `spawn_upstream`, counting the connections it accepted in `connections`
+pub fn spawn_counting_upstream<F, R>(connections: Arc<AtomicUsize>, handler: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    serve(local_listener(), false, Some(connections), handler)
+}
+
+fn local_listener() -> TcpListener {
+    TcpListener::bind("127.0.0.1:0").unwrap()
+}
+
+fn serve<F, R>(
+    listener: TcpListener,
+    http2_only: bool,
+    connections: Option<Arc<AtomicUsize>>,
+    handler: F,
+) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_svc = make_service_fn(move |_| {
+        if let Some(connections) = &connections {
+            connections.fetch_add(1, Ordering::SeqCst);
+        }
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let res = handler(req);
+                async move { Ok::<_, Infallible>(res.await) }
+            }))
+        }
+    });
+    let addr = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let server = Server::from_tcp(listener)
+        .unwrap()
+        .http2_only(http2_only)
+        .serve(make_svc);
+    tokio::spawn(server);
+    addr
+}
+
+/// Stub upstream answering the headers it received, one `name: value` per line in order,
+/// with a few headers of its own
+pub fn spawn_headers_upstream() -> SocketAddr {
+    spawn_upstream(|req| async move {
+        let mut lines: Vec<String> = req
+            .headers()
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value.to_str().unwrap()))
+            .collect();
+        lines.sort();
+        Response::builder()
+            .header("X-Internal", "secret")
+            .header("Keep-Alive", "timeout=5")
+            .header("Server", "upstream")
+            .body(Body::from(lines.join("\n")))
+            .unwrap()
+    })
+}
+
+/// Request headers an upstream from `spawn_headers_upstream` received, from its answer
+pub fn received_headers(body: &str) -> Vec<String> {
+    body.lines().map(String::from).collect()
+}
+
+/// Sends every request to `/` on the upstream
+pub struct Forward(pub SocketAddr);
+
+impl Middleware for Forward {
+    fn name() -> String {
+        String::from("Forward")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        *req.uri_mut() = format!("http://{}/", self.0).parse::<Uri>()?;
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+/// A service for a client at `127.0.0.1:4242`
+pub fn service(middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>>) -> ProxyService {
+    service_from(([127, 0, 0, 1], 4242).into(), middlewares)
+}
+
+/// A service for a client at `client`
+pub fn service_from(
+    client: SocketAddr,
+    middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>>,
+) -> ProxyService {
+    ProxyService::new(Arc::new(middlewares), client)
+}
+
+/// Sends `req` once the service is ready, the response body is left to read
+pub async fn call(
+    service: &mut ProxyService,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    service.call(req).await
+}
+
+/// The response, and its body read apart
+pub async fn send(service: &mut ProxyService, req: Request<Body>) -> (Response<Body>, String) {
+    let mut res = call(service, req).await.unwrap();
+    let body = std::mem::replace(res.body_mut(), Body::empty());
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    (res, String::from_utf8(body.to_vec()).unwrap())
+}
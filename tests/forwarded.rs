@@ -0,0 +1,153 @@
+mod common;
+
+use common::{received_headers, spawn_headers_upstream, Forward};
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::forwarded::{Forwarded, ForwardedConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::net::SocketAddr;
+
+/// A service for a client at `client`, accepted on port 8080
+fn service(config: ForwardedConfig, client: SocketAddr) -> ProxyService {
+    let upstream = spawn_headers_upstream();
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![
+        Box::new(Forwarded::new(config)),
+        Box::new(Forward(upstream)),
+    ];
+    let mut service = common::service_from(client, middlewares);
+    service.set_local_addr(([127, 0, 0, 1], 8080).into());
+    service
+}
+
+/// The response, and the request headers the upstream received
+async fn send(
+    service: &mut ProxyService,
+    req: http::request::Builder,
+) -> (Response<Body>, Vec<String>) {
+    let req = req.header("host", "proxy.example.com").body(Body::empty());
+    let (res, body) = common::send(service, req.unwrap()).await;
+    (res, received_headers(&body))
+}
+
+fn has(headers: &[String], header: &str) -> bool {
+    headers.iter().any(|h| h == header)
+}
+
+#[tokio::test]
+async fn untrusted_forwarding_headers_are_replaced() {
+    let mut service = service(ForwardedConfig::default(), ([10, 0, 0, 7], 4242).into());
+
+    let req = Request::get("/")
+        .header("X-Forwarded-For", "1.2.3.4")
+        .header("X-Forwarded-Proto", "https")
+        .header("Forwarded", "for=1.2.3.4");
+    let (_, headers) = send(&mut service, req).await;
+
+    assert!(has(&headers, "x-forwarded-for: 10.0.0.7"), "{:?}", headers);
+    assert!(has(&headers, "x-forwarded-proto: http"), "{:?}", headers);
+    assert!(has(&headers, "x-forwarded-port: 8080"), "{:?}", headers);
+    assert!(!headers.iter().any(|h| h.starts_with("forwarded:")));
+}
+
+#[tokio::test]
+async fn trusted_proxies_headers_are_appended_to() {
+    let config = ForwardedConfig {
+        trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+        forwarded: true,
+        ..ForwardedConfig::default()
+    };
+    let mut service = service(config, ([10, 0, 0, 7], 4242).into());
+
+    let req = Request::get("/")
+        .header("X-Forwarded-For", "1.2.3.4")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Port", "443")
+        .header("Forwarded", "for=1.2.3.4;proto=https");
+    let (_, headers) = send(&mut service, req).await;
+
+    assert!(
+        has(&headers, "x-forwarded-for: 1.2.3.4, 10.0.0.7"),
+        "{:?}",
+        headers
+    );
+    assert!(has(&headers, "x-forwarded-proto: https"), "{:?}", headers);
+    assert!(has(&headers, "x-forwarded-port: 443"), "{:?}", headers);
+    assert!(
+        has(
+            &headers,
+            "forwarded: for=1.2.3.4;proto=https, for=10.0.0.7;proto=http;host=\"proxy.example.com\""
+        ),
+        "{:?}",
+        headers
+    );
+}
+
+#[tokio::test]
+async fn forwarded_quotes_ipv6_clients() {
+    let config = ForwardedConfig {
+        forwarded: true,
+        ..ForwardedConfig::default()
+    };
+    let client: SocketAddr = "[2001:db8::1]:4242".parse().unwrap();
+    let mut service = service(config, client);
+    service.set_tls(true);
+
+    let (_, headers) = send(&mut service, Request::get("/")).await;
+
+    assert!(
+        has(&headers, "x-forwarded-for: 2001:db8::1"),
+        "{:?}",
+        headers
+    );
+    assert!(has(&headers, "x-forwarded-proto: https"), "{:?}", headers);
+    assert!(
+        has(
+            &headers,
+            "forwarded: for=\"[2001:db8::1]\";proto=https;host=\"proxy.example.com\""
+        ),
+        "{:?}",
+        headers
+    );
+}
+
+#[tokio::test]
+async fn hosts_that_are_not_authorities_are_left_out() {
+    let config = ForwardedConfig {
+        forwarded: true,
+        ..ForwardedConfig::default()
+    };
+    let mut service = service(config, ([10, 0, 0, 7], 4242).into());
+
+    // Would end the quoted host and add an element with a spoofed client
+    let req = Request::get("/")
+        .header("host", r#"a", for=6.6.6.6;x=""#)
+        .body(Body::empty())
+        .unwrap();
+    let (_, body) = common::send(&mut service, req).await;
+    let headers = received_headers(&body);
+
+    assert!(
+        has(&headers, "forwarded: for=10.0.0.7;proto=http"),
+        "{:?}",
+        headers
+    );
+}
+
+#[tokio::test]
+async fn via_is_added_to_requests_and_responses() {
+    let config = ForwardedConfig {
+        via: Some(String::from("simple-proxy")),
+        ..ForwardedConfig::default()
+    };
+    let mut service = service(config, ([10, 0, 0, 7], 4242).into());
+
+    let req = Request::get("/").header("Via", "1.0 edge");
+    let (res, headers) = send(&mut service, req).await;
+
+    assert!(
+        has(&headers, "via: 1.0 edge, 1.1 simple-proxy"),
+        "{:?}",
+        headers
+    );
+    assert_eq!(res.headers()["via"], "1.1 simple-proxy");
+}
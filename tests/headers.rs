@@ -0,0 +1,257 @@
+mod common;
+
+use common::{received_headers, spawn_headers_upstream, Forward};
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::headers::{HeaderRules, Headers, HeadersConfig, HeadersError};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::collections::HashMap;
+
+fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn service(headers: Headers) -> ProxyService {
+    let upstream = spawn_headers_upstream();
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> =
+        vec![Box::new(Forward(upstream)), Box::new(headers)];
+    common::service_from(([10, 0, 0, 7], 4242).into(), middlewares)
+}
+
+/// The response, and the request headers the upstream received
+async fn send(service: &mut ProxyService, req: Request<Body>) -> (Response<Body>, Vec<String>) {
+    let (res, body) = common::send(service, req).await;
+    (res, received_headers(&body))
+}
+
+#[tokio::test]
+async fn request_headers_are_changed() {
+    let config = HeadersConfig {
+        request: HeaderRules {
+            remove: vec![String::from("Cookie")],
+            rename: map(&[("X-Old", "X-New")]),
+            set: map(&[("X-Client-Ip", "{client_ip}"), ("X-Env", "prod")]),
+            add: map(&[("X-Tag", "{method}-proxy")]),
+        },
+        ..HeadersConfig::default()
+    };
+    let mut service = service(Headers::new(config).unwrap());
+
+    let req = Request::post("/")
+        .header("Cookie", "session=1")
+        .header("X-Old", "kept")
+        .header("X-Env", "dev")
+        .header("X-Tag", "client")
+        .body(Body::empty())
+        .unwrap();
+    let (_, headers) = send(&mut service, req).await;
+
+    assert!(!headers.iter().any(|h| h.starts_with("cookie:")));
+    assert!(!headers.iter().any(|h| h.starts_with("x-old:")));
+    assert!(headers.contains(&String::from("x-new: kept")));
+    assert!(headers.contains(&String::from("x-client-ip: 10.0.0.7")));
+    assert!(headers.contains(&String::from("x-env: prod")));
+    assert!(!headers.contains(&String::from("x-env: dev")));
+    assert!(headers.contains(&String::from("x-tag: client")));
+    assert!(headers.contains(&String::from("x-tag: POST-proxy")));
+}
+
+#[tokio::test]
+async fn response_headers_are_changed() {
+    let config = HeadersConfig {
+        response: HeaderRules {
+            remove: vec![String::from("X-Internal")],
+            rename: map(&[("Server", "X-Upstream-Server")]),
+            set: map(&[("X-Served-For", "{method} from {client_ip}")]),
+            ..HeaderRules::default()
+        },
+        ..HeadersConfig::default()
+    };
+    let mut service = service(Headers::new(config).unwrap());
+
+    let req = Request::get("/").body(Body::empty()).unwrap();
+    let (res, _) = send(&mut service, req).await;
+
+    let headers = res.headers();
+    assert!(headers.get("x-internal").is_none());
+    assert!(headers.get("server").is_none());
+    assert_eq!(headers["x-upstream-server"], "upstream");
+    assert_eq!(headers["x-served-for"], "GET from 10.0.0.7");
+}
+
+#[tokio::test]
+async fn hop_by_hop_headers_are_stripped() {
+    let mut service = service(Headers::new(HeadersConfig::default()).unwrap());
+
+    let req = Request::get("/")
+        .header("Connection", "X-Trace, keep-alive")
+        .header("X-Trace", "1")
+        .header("Keep-Alive", "timeout=5")
+        .header("Proxy-Authorization", "Basic Zm9vOmJhcg==")
+        .header("TE", "trailers")
+        .header("X-Kept", "1")
+        .body(Body::empty())
+        .unwrap();
+    let (res, headers) = send(&mut service, req).await;
+
+    for name in [
+        "connection:",
+        "x-trace:",
+        "keep-alive:",
+        "proxy-authorization:",
+    ] {
+        assert!(!headers.iter().any(|h| h.starts_with(name)), "{}", name);
+    }
+    assert!(headers.contains(&String::from("te: trailers")));
+    assert!(headers.contains(&String::from("x-kept: 1")));
+    assert!(res.headers().get("keep-alive").is_none());
+}
+
+#[tokio::test]
+async fn hop_by_hop_headers_can_be_kept() {
+    let mut headers = Headers::new(HeadersConfig::default()).unwrap();
+    headers.set_strip_hop_by_hop(false);
+    let mut service = service(headers);
+
+    let req = Request::get("/")
+        .header("Proxy-Authorization", "Basic Zm9vOmJhcg==")
+        .body(Body::empty())
+        .unwrap();
+    let (res, headers) = send(&mut service, req).await;
+
+    assert!(headers
+        .iter()
+        .any(|h| h.starts_with("proxy-authorization:")));
+    assert_eq!(res.headers()["keep-alive"], "timeout=5");
+}
+
+#[cfg(feature = "router")]
+#[tokio::test]
+async fn route_rules_apply_after_the_global_ones() {
+    use simple_proxy::middlewares::router::{Router, RouterConfig};
+
+    struct Config(String);
+
+    impl RouterConfig for Config {
+        fn get_router_filename(&self) -> &str {
+            &self.0
+        }
+    }
+
+    let upstream = spawn_headers_upstream();
+    let config = format!(
+        r#"{{
+            "rules": [{{
+                "from": {{ "host": ".*", "path": "(.*)" }},
+                "to": {{ "host": "{}", "path": "$1" }},
+                "public": true,
+                "headers": {{
+                    "request": {{ "set": {{ "X-Env": "route", "X-Route": "{{route}}" }} }},
+                    "response": {{ "remove": ["X-Upstream-Server"] }}
+                }}
+            }}]
+        }}"#,
+        upstream
+    );
+    let path =
+        std::env::temp_dir().join(format!("simple_proxy_headers_{}.json", std::process::id()));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+
+    let global = HeadersConfig {
+        request: HeaderRules {
+            set: map(&[("X-Env", "global")]),
+            ..HeaderRules::default()
+        },
+        response: HeaderRules {
+            rename: map(&[("Server", "X-Upstream-Server")]),
+            ..HeaderRules::default()
+        },
+    };
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> =
+        vec![Box::new(router), Box::new(Headers::new(global).unwrap())];
+    let mut service = common::service_from(([10, 0, 0, 7], 4242).into(), middlewares);
+
+    let req = Request::get("/items?page=2")
+        .header("host", "proxy")
+        .body(Body::empty())
+        .unwrap();
+    let (res, headers) = send(&mut service, req).await;
+
+    assert!(headers.contains(&String::from("x-env: route")));
+    let route = format!("x-route: http://{}/items?page=2", upstream);
+    assert!(headers.contains(&route), "{:?}", headers);
+    assert!(res.headers().get("server").is_none());
+    assert!(res.headers().get("x-upstream-server").is_none());
+}
+
+#[test]
+fn invalid_names_and_values_are_rejected() {
+    let config = HeadersConfig {
+        request: HeaderRules {
+            rename: map(&[("X-Old", "X New")]),
+            ..HeaderRules::default()
+        },
+        ..HeadersConfig::default()
+    };
+    assert_eq!(
+        Headers::new(config).err(),
+        Some(HeadersError::InvalidName {
+            name: String::from("X New")
+        })
+    );
+
+    let config = HeadersConfig {
+        response: HeaderRules {
+            add: map(&[("X-Served-By", "proxy\n{client_ip}")]),
+            ..HeaderRules::default()
+        },
+        ..HeadersConfig::default()
+    };
+    assert_eq!(
+        Headers::new(config).err(),
+        Some(HeadersError::InvalidValue {
+            name: String::from("X-Served-By"),
+            value: String::from("proxy\n{client_ip}")
+        })
+    );
+}
+
+#[cfg(feature = "router")]
+#[test]
+fn invalid_route_headers_are_rejected() {
+    use simple_proxy::middlewares::router::{Router, RouterConfig, RouterError};
+
+    struct Config(String);
+
+    impl RouterConfig for Config {
+        fn get_router_filename(&self) -> &str {
+            &self.0
+        }
+    }
+
+    let config = r#"{
+        "rules": [{
+            "from": { "host": ".*", "path": "(.*)" },
+            "to": { "host": "upstream", "path": "$1" },
+            "public": true,
+            "headers": { "request": { "remove": ["X Bad"] } }
+        }]
+    }"#;
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_headers_invalid_{}.json",
+        std::process::id()
+    ));
+    std::fs::write(&path, config).unwrap();
+    let err = Router::try_new(&Config(path.to_str().unwrap().to_string())).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        matches!(err, RouterError::InvalidRule { index: 0, .. }),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,198 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use hyper::{Body, Request, Response, StatusCode};
+use simple_proxy::middlewares::router::{Router, RouterConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Stub backend answering its name, `/health` answers 500 while `healthy` is false
+fn spawn_backend(name: &'static str, healthy: Arc<AtomicBool>) -> SocketAddr {
+    common::spawn_upstream(move |req: Request<Body>| {
+        let healthy = healthy.load(Ordering::SeqCst);
+        async move {
+            let mut res = Response::new(Body::from(name));
+            if req.uri().path() == "/health" && !healthy {
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            res
+        }
+    })
+}
+
+/// Stub backend only speaking HTTP/2 (h2c), answering its name
+fn spawn_h2_backend(name: &'static str) -> SocketAddr {
+    common::spawn_h2_upstream(move |_req| async move { Response::new(Body::from(name)) })
+}
+
+/// Address nothing listens on
+fn closed_port() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Routes everything to a round robin pool of `backends` checked with `probe`
+fn router(probe: &str, backends: &[SocketAddr]) -> Router {
+    router_with_http2(probe, backends, false)
+}
+
+fn router_with_http2(probe: &str, backends: &[SocketAddr], http2: bool) -> Router {
+    let backends: Vec<String> = backends
+        .iter()
+        .map(|addr| format!(r#"{{ "host": "{}" }}"#, addr))
+        .collect();
+    let config = format!(
+        r#"{{
+            "rules": [{{
+                "from": {{ "host": ".*", "path": "(.*)" }},
+                "to": {{ "host": "unused", "path": "$1" }},
+                "public": true,
+                "upstream": "pool",
+                "http2": {}
+            }}],
+            "upstreams": {{
+                "pool": {{
+                    "backends": [{}],
+                    "health_check": {{
+                        "probe": {},
+                        "interval_ms": 20,
+                        "timeout_ms": 200,
+                        "unhealthy_threshold": 2,
+                        "healthy_threshold": 2
+                    }}
+                }}
+            }}
+        }}"#,
+        http2,
+        backends.join(","),
+        probe
+    );
+
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_hc_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+    router
+}
+
+fn service(router: Router) -> ProxyService {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+    common::service(middlewares)
+}
+
+async fn send(service: &mut ProxyService) -> (StatusCode, String) {
+    let req = Request::get("/").header("host", "proxy");
+    let (res, body) = common::send(service, req.body(Body::empty()).unwrap()).await;
+    (res.status(), body)
+}
+
+async fn answers(service: &mut ProxyService, count: usize) -> HashSet<String> {
+    let mut answers = HashSet::new();
+    for _ in 0..count {
+        answers.insert(send(service).await.1);
+    }
+    answers
+}
+
+/// Leaves time for a few probes
+async fn wait_for_probes() {
+    tokio::time::sleep(Duration::from_millis(300)).await;
+}
+
+#[tokio::test]
+async fn http_probe_skips_unhealthy_backends_until_they_recover() {
+    let b_healthy = Arc::new(AtomicBool::new(false));
+    let a = spawn_backend("a", Arc::new(AtomicBool::new(true)));
+    let b = spawn_backend("b", Arc::clone(&b_healthy));
+    let mut service = service(router(
+        r#"{ "http": { "path": "/health", "status": 200 } }"#,
+        &[a, b],
+    ));
+
+    send(&mut service).await;
+    wait_for_probes().await;
+    assert_eq!(
+        answers(&mut service, 6).await,
+        HashSet::from(["a".to_string()])
+    );
+
+    b_healthy.store(true, Ordering::SeqCst);
+    wait_for_probes().await;
+    assert_eq!(
+        answers(&mut service, 6).await,
+        HashSet::from(["a".to_string(), "b".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn tcp_probe_skips_backends_refusing_connections() {
+    let a = spawn_backend("a", Arc::new(AtomicBool::new(true)));
+    let mut service = service(router(r#""tcp""#, &[a, closed_port()]));
+
+    send(&mut service).await;
+    wait_for_probes().await;
+    assert_eq!(
+        answers(&mut service, 6).await,
+        HashSet::from(["a".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn no_healthy_backend_is_unavailable() {
+    let a = spawn_backend("a", Arc::new(AtomicBool::new(false)));
+    let mut service = service(router(r#"{ "http": { "path": "/health" } }"#, &[a]));
+
+    send(&mut service).await;
+    wait_for_probes().await;
+    assert_eq!(send(&mut service).await.0, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn checks_start_with_the_rules() {
+    let a = spawn_backend("a", Arc::new(AtomicBool::new(true)));
+    let b = spawn_backend("b", Arc::new(AtomicBool::new(false)));
+    let mut service = service(router(r#"{ "http": { "path": "/health" } }"#, &[a, b]));
+
+    // Before any request
+    wait_for_probes().await;
+    assert_eq!(
+        answers(&mut service, 6).await,
+        HashSet::from(["a".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn http_probes_use_the_route_http_version() {
+    let a = spawn_h2_backend("a");
+    let mut service = service(router_with_http2(
+        r#"{ "http": { "path": "/health" } }"#,
+        &[a],
+        true,
+    ));
+
+    // HTTP/1 probes would mark the only backend unhealthy
+    for _ in 0..2 {
+        wait_for_probes().await;
+        assert_eq!(send(&mut service).await, (StatusCode::OK, "a".to_string()));
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use hyper::{Body, Client, Request, Response, Uri, Version};
+use simple_proxy::proxy::client::UpstreamVersion;
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use {
+    simple_proxy::tls::TlsConfig,
+    std::convert::TryFrom,
+    std::sync::Arc,
+    tokio::net::TcpStream,
+    tokio_rustls::rustls::{Certificate, ClientConfig, RootCertStore, ServerName},
+    tokio_rustls::TlsConnector,
+};
+
+/// Only speaks HTTP/2, answers with the version it received
+fn spawn_h2_upstream() -> SocketAddr {
+    common::spawn_h2_upstream(|req| async move {
+        Response::new(Body::from(format!("{:?}", req.version())))
+    })
+}
+
+struct ToH2Upstream(SocketAddr);
+
+impl Middleware for ToH2Upstream {
+    fn name() -> String {
+        String::from("ToH2Upstream")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        *req.uri_mut() = format!("http://{}{}", self.0, req.uri().path()).parse::<Uri>()?;
+        state.insert(UpstreamVersion::Http2);
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+#[tokio::test]
+async fn proxies_prior_knowledge_http2_to_http2_upstreams() {
+    let upstream = spawn_h2_upstream();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.add_middleware(Box::new(ToH2Upstream(upstream)));
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let res = client
+        .get(format!("http://127.0.0.1:{}/grpc", port).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.version(), Version::HTTP_2);
+
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "HTTP/2.0");
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn proxies_http2_negotiated_through_alpn() {
+    let upstream = spawn_h2_upstream();
+
+    let dir = std::env::temp_dir().join(format!("simple_proxy_alpn_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let pem = cert.serialize_pem().unwrap();
+    let cert_path = dir.join("localhost.crt").to_str().unwrap().to_string();
+    let key_path = dir.join("localhost.key").to_str().unwrap().to_string();
+    std::fs::write(&cert_path, &pem).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.set_tls(TlsConfig::from_pem_files(&cert_path, &key_path).unwrap());
+    proxy.add_middleware(Box::new(ToH2Upstream(upstream)));
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Only offers h2, as gRPC clients do
+    let mut roots = RootCertStore::empty();
+    let der = rustls_pemfile::certs(&mut pem.as_bytes())
+        .unwrap()
+        .remove(0);
+    roots.add(&Certificate(der)).unwrap();
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let tls = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    let (mut sender, conn) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(tls)
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let req = Request::get(format!("https://localhost:{}/grpc", port))
+        .body(Body::empty())
+        .unwrap();
+    let res = sender.send_request(req).await.unwrap();
+    assert_eq!(res.version(), Version::HTTP_2);
+
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body, "HTTP/2.0");
+}
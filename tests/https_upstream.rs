@@ -0,0 +1,407 @@
+#![cfg(all(feature = "tls", feature = "https"))]
+
+mod common;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use simple_proxy::proxy::client::{Clients, UpstreamTls, UpstreamTlsOptions};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use simple_proxy::tls::{TlsConfig, TlsError};
+use simple_proxy::{Environment, SimpleProxy};
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+struct Hello;
+
+impl Middleware for Hello {
+    fn name() -> String {
+        String::from("Hello")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(MiddlewareResult::RespondWith(Response::new(Body::from(
+            "hello over tls",
+        ))))
+    }
+}
+
+/// Sends every request to `upstream` with the given TLS settings
+struct ToUpstream {
+    upstream: Uri,
+    tls: UpstreamTls,
+}
+
+impl Middleware for ToUpstream {
+    fn name() -> String {
+        String::from("ToUpstream")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        *req.uri_mut() = self.upstream.clone();
+        state.insert(self.tls.clone());
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+/// Starts an HTTPS upstream for `localhost`, returns its port and certificate path
+async fn spawn_upstream() -> (u16, String) {
+    let dir = std::env::temp_dir().join(format!("simple_proxy_https_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let cert_path = dir.join("localhost.crt").to_str().unwrap().to_string();
+    let key_path = dir.join("localhost.key").to_str().unwrap().to_string();
+    std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut upstream = SimpleProxy::new(port, Environment::Development);
+    upstream.add_middleware(Box::new(Hello));
+    upstream.set_tls(TlsConfig::from_pem_files(&cert_path, &key_path).unwrap());
+    tokio::spawn(async move { upstream.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    (port, cert_path)
+}
+
+static UPSTREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// PEM files of an upstream requiring client certificates, all issued by one CA
+struct MutualTls {
+    ca: String,
+    client_cert: String,
+    client_key: String,
+}
+
+fn write_pem(dir: &Path, name: &str, pem: String) -> String {
+    let path = dir.join(name).to_str().unwrap().to_string();
+    std::fs::write(&path, pem).unwrap();
+    path
+}
+
+/// Starts an HTTPS upstream for `localhost` only answering clients with a certificate
+/// issued by its CA, returns its port and the PEM files to reach it
+async fn spawn_mtls_upstream() -> (u16, MutualTls) {
+    let dir = std::env::temp_dir().join(format!(
+        "simple_proxy_mtls_{}_{}",
+        std::process::id(),
+        UPSTREAMS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+    let server =
+        Certificate::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+    let client = Certificate::from_params(CertificateParams::new(vec!["proxy".into()])).unwrap();
+
+    let files = MutualTls {
+        ca: write_pem(&dir, "ca.crt", ca.serialize_pem().unwrap()),
+        client_cert: write_pem(
+            &dir,
+            "client.crt",
+            client.serialize_pem_with_signer(&ca).unwrap(),
+        ),
+        client_key: write_pem(&dir, "client.key", client.serialize_private_key_pem()),
+    };
+
+    let mut client_roots = RootCertStore::empty();
+    client_roots
+        .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+        .unwrap();
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots).boxed())
+        .with_single_cert(
+            vec![rustls::Certificate(
+                server.serialize_der_with_signer(&ca).unwrap(),
+            )],
+            rustls::PrivateKey(server.serialize_private_key_der()),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    let hello = service_fn(|_| async {
+                        Ok::<_, Infallible>(Response::new(Body::from("hello over mtls")))
+                    });
+                    let _ = Http::new().serve_connection(stream, hello).await;
+                }
+            });
+        }
+    });
+
+    (port, files)
+}
+
+async fn get_through(port: u16, options: UpstreamTlsOptions) -> Result<String, hyper::Error> {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(ToUpstream {
+        upstream: format!("https://localhost:{}/", port).parse().unwrap(),
+        tls: UpstreamTls::new(&options).unwrap(),
+    })];
+    let mut service = common::service(middlewares);
+
+    let res = common::call(&mut service, Request::get("/").body(Body::empty()).unwrap()).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    Ok(String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn reaches_https_upstreams() {
+    let (port, cert_path) = spawn_upstream().await;
+
+    // Trusted through its CA bundle
+    let body = get_through(
+        port,
+        UpstreamTlsOptions {
+            ca_file: Some(cert_path),
+            ..UpstreamTlsOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(body, "hello over tls");
+
+    // Unknown to the default web roots
+    assert!(get_through(port, UpstreamTlsOptions::default())
+        .await
+        .is_err());
+
+    // Unless verification is skipped
+    let body = get_through(
+        port,
+        UpstreamTlsOptions {
+            insecure_skip_verify: true,
+            ..UpstreamTlsOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(body, "hello over tls");
+}
+
+#[tokio::test]
+async fn sends_client_certificates_to_mtls_upstreams() {
+    let (port, files) = spawn_mtls_upstream().await;
+
+    let body = get_through(
+        port,
+        UpstreamTlsOptions {
+            ca_file: Some(files.ca.clone()),
+            client_cert: Some(files.client_cert),
+            client_key: Some(files.client_key),
+            ..UpstreamTlsOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(body, "hello over mtls");
+
+    // Rejected without a client certificate
+    let anonymous = UpstreamTlsOptions {
+        ca_file: Some(files.ca),
+        ..UpstreamTlsOptions::default()
+    };
+    assert!(get_through(port, anonymous).await.is_err());
+}
+
+#[test]
+fn client_certificate_needs_its_key() {
+    let options = UpstreamTlsOptions {
+        client_cert: Some(String::from("client.crt")),
+        ..UpstreamTlsOptions::default()
+    };
+    assert!(matches!(
+        UpstreamTls::new(&options),
+        Err(TlsError::IncompleteClientAuth { path }) if path == "client.crt"
+    ));
+}
+
+#[cfg(feature = "router")]
+mod router {
+    use super::*;
+    use simple_proxy::middlewares::router::{Router, RouterConfig, RouterError};
+
+    struct Config(String);
+
+    impl RouterConfig for Config {
+        fn get_router_filename(&self) -> &str {
+            &self.0
+        }
+    }
+
+    /// Loads a router sending `mtls.example.com` to the upstream with the given `tls` block
+    fn try_router(name: &str, port: u16, tls: &str) -> Result<Router, RouterError> {
+        let path = std::env::temp_dir().join(format!(
+            "simple_proxy_mtls_{}_{}.json",
+            std::process::id(),
+            name
+        ));
+        let rules = format!(
+            r#"{{ "rules": [{{
+                "from": {{ "host": "mtls.example.com", "path": "(.*)" }},
+                "to": {{ "host": "https://localhost:{}", "path": "$1" }},
+                "public": true,
+                "tls": {}
+            }}] }}"#,
+            port, tls
+        );
+        std::fs::write(&path, rules).unwrap();
+        let router = Router::try_new(&Config(path.to_str().unwrap().to_string()));
+        std::fs::remove_file(&path).unwrap();
+        router
+    }
+
+    #[tokio::test]
+    async fn routes_reach_mtls_upstreams_with_their_tls_block() {
+        let (port, files) = spawn_mtls_upstream().await;
+        let tls = format!(
+            r#"{{ "ca_file": "{}", "client_cert": "{}", "client_key": "{}" }}"#,
+            files.ca, files.client_cert, files.client_key
+        );
+        let router = try_router("valid", port, &tls).unwrap();
+
+        let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+        let mut service = common::service(middlewares);
+        let req = Request::get("/")
+            .header("host", "mtls.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let (res, body) = common::send(&mut service, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body, "hello over mtls");
+    }
+
+    #[tokio::test]
+    async fn reloads_do_not_pile_up_tls_clients() {
+        let (port, files) = spawn_mtls_upstream().await;
+        let path = std::env::temp_dir().join(format!(
+            "simple_proxy_mtls_{}_reloaded.json",
+            std::process::id()
+        ));
+        let rules = format!(
+            r#"{{ "rules": [{{
+                "from": {{ "host": "mtls.example.com", "path": "(.*)" }},
+                "to": {{ "host": "https://localhost:{}", "path": "$1" }},
+                "public": true,
+                "tls": {{ "ca_file": "{}", "client_cert": "{}", "client_key": "{}" }}
+            }}] }}"#,
+            port, files.ca, files.client_cert, files.client_key
+        );
+        std::fs::write(&path, rules).unwrap();
+        let router = Router::try_new(&Config(path.to_str().unwrap().to_string())).unwrap();
+
+        let clients = Arc::new(Clients::new());
+        let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> =
+            vec![Box::new(router.clone())];
+        let mut service = ProxyService::with_clients(
+            Arc::new(middlewares),
+            ([127, 0, 0, 1], 4242).into(),
+            Arc::clone(&clients),
+        );
+        for _ in 0..5 {
+            router.reload().unwrap();
+            let req = Request::get("/")
+                .header("host", "mtls.example.com")
+                .body(Body::empty())
+                .unwrap();
+            let (res, _) = common::send(&mut service, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            // Only the client of the current rules is kept
+            assert_eq!(clients.cached_clients(), 1);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn health_checks_probe_with_the_route_tls_block() {
+        let (port, files) = spawn_mtls_upstream().await;
+        let path = std::env::temp_dir().join(format!(
+            "simple_proxy_mtls_{}_probed.json",
+            std::process::id()
+        ));
+        let rules = format!(
+            r#"{{
+                "rules": [{{
+                    "from": {{ "host": "mtls.example.com", "path": "(.*)" }},
+                    "to": {{ "host": "unused", "path": "$1" }},
+                    "public": true,
+                    "upstream": "pool",
+                    "tls": {{ "ca_file": "{}", "client_cert": "{}", "client_key": "{}" }}
+                }}],
+                "upstreams": {{
+                    "pool": {{
+                        "backends": [{{ "host": "https://localhost:{}" }}],
+                        "health_check": {{
+                            "probe": {{ "http": {{ "path": "/health" }} }},
+                            "interval_ms": 20,
+                            "unhealthy_threshold": 2
+                        }}
+                    }}
+                }}
+            }}"#,
+            files.ca, files.client_cert, files.client_key, port
+        );
+        std::fs::write(&path, rules).unwrap();
+        let router = Router::try_new(&Config(path.to_str().unwrap().to_string())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Probes without the client certificate would mark the only backend unhealthy
+        let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+        let mut service = common::service(middlewares);
+        for _ in 0..2 {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let req = Request::get("/")
+                .header("host", "mtls.example.com")
+                .body(Body::empty())
+                .unwrap();
+            let res = common::call(&mut service, req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn routes_with_a_client_certificate_without_key_are_rejected() {
+        let tls = r#"{ "client_cert": "client.crt" }"#;
+        let err = try_router("incomplete", 443, tls).unwrap_err();
+        assert!(
+            matches!(err, RouterError::InvalidUpstreamTls { index: 0, .. }),
+            "{}",
+            err
+        );
+    }
+}
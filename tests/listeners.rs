@@ -0,0 +1,111 @@
+use hyper::{Body, Client, Request, Response};
+use simple_proxy::listener::Listener;
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+/// Answers with its name and the client address
+struct Answer(&'static str);
+
+impl Middleware for Answer {
+    fn name() -> String {
+        String::from("Answer")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(MiddlewareResult::RespondWith(Response::new(Body::from(
+            format!("{} {}", self.0, ctx.remote_addr.ip()),
+        ))))
+    }
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn body(res: Response<Body>) -> String {
+    String::from_utf8(
+        hyper::body::to_bytes(res.into_body())
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn serves_several_listeners_with_their_own_middlewares() {
+    let public = free_addr();
+    let internal = free_addr();
+
+    let mut proxy = SimpleProxy::bind(public, Environment::Development);
+    proxy.add_middleware(Box::new(Answer("public")));
+
+    let mut internal_listener = Listener::tcp(internal);
+    internal_listener.add_middleware(Box::new(Answer("internal")));
+    proxy.add_listener(internal_listener);
+
+    #[cfg(unix)]
+    let socket_path =
+        std::env::temp_dir().join(format!("simple_proxy_{}.sock", std::process::id()));
+    #[cfg(unix)]
+    proxy.add_listener(Listener::unix(&socket_path));
+
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let res = client
+        .get(format!("http://{}/", public).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(body(res).await, "public 127.0.0.1");
+
+    let res = client
+        .get(format!("http://{}/", internal).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(body(res).await, "internal 127.0.0.1");
+
+    #[cfg(unix)]
+    {
+        let stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+        tokio::spawn(conn);
+        let res = sender
+            .send_request(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(body(res).await, "public 127.0.0.1");
+    }
+}
+
+#[tokio::test]
+async fn binds_to_the_given_interface_only() {
+    let addr = free_addr();
+    let mut proxy = SimpleProxy::bind(addr, Environment::Development);
+    proxy.add_middleware(Box::new(Answer("local")));
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let res = Client::new()
+        .get(format!("http://{}/", addr).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(body(res).await, "local 127.0.0.1");
+
+    // Not reachable through another local address
+    let other = format!("http://127.0.0.2:{}/", addr.port());
+    assert!(Client::new().get(other.parse().unwrap()).await.is_err());
+}
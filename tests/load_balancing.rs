@@ -0,0 +1,218 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use futures::future::join_all;
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::router::{Router, RouterConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Stub backend answering its name, after `delay`
+fn spawn_backend(name: &'static str, delay: Duration) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move {
+        tokio::time::sleep(delay).await;
+        Response::new(Body::from(name))
+    })
+}
+
+/// Stub backend answering its name right away, then the rest of the body after `delay`
+fn spawn_streaming_backend(name: &'static str, delay: Duration) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            sender.send_data(name.into()).await.unwrap();
+            tokio::time::sleep(delay).await;
+            let _ = sender.send_data(" done".into()).await;
+        });
+        Response::new(body)
+    })
+}
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Routes everything to a pool of `backends` (address, weight) balanced with `strategy`
+fn router(strategy: &str, backends: &[(SocketAddr, u32)]) -> Router {
+    let backends: Vec<String> = backends
+        .iter()
+        .map(|(addr, weight)| format!(r#"{{ "host": "{}", "weight": {} }}"#, addr, weight))
+        .collect();
+    let config = format!(
+        r#"{{
+            "rules": [{{
+                "from": {{ "host": ".*", "path": "(.*)" }},
+                "to": {{ "host": "unused", "path": "$1" }},
+                "public": true,
+                "upstream": "pool"
+            }}],
+            "upstreams": {{
+                "pool": {{ "strategy": {}, "backends": [{}] }}
+            }}
+        }}"#,
+        strategy,
+        backends.join(",")
+    );
+
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_lb_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+    router
+}
+
+fn service(router: Router, client_ip: [u8; 4]) -> ProxyService {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+    common::service_from((client_ip, 4242).into(), middlewares)
+}
+
+async fn send(service: &mut ProxyService, user: Option<&str>) -> String {
+    let mut req = Request::get("/").header("host", "proxy");
+    if let Some(user) = user {
+        req = req.header("x-user", user);
+    }
+    common::send(service, req.body(Body::empty()).unwrap())
+        .await
+        .1
+}
+
+/// Sends `count` sequential requests, returns how many each backend answered
+async fn distribution(service: &mut ProxyService, count: usize) -> HashMap<String, usize> {
+    let mut answers = HashMap::new();
+    for _ in 0..count {
+        *answers.entry(send(service, None).await).or_insert(0) += 1;
+    }
+    answers
+}
+
+async fn three_backends() -> Vec<SocketAddr> {
+    vec![
+        spawn_backend("a", Duration::from_millis(0)),
+        spawn_backend("b", Duration::from_millis(0)),
+        spawn_backend("c", Duration::from_millis(0)),
+    ]
+}
+
+#[tokio::test]
+async fn round_robin_uses_each_backend_in_turn() {
+    let backends = three_backends().await;
+    let pool: Vec<_> = backends.iter().map(|addr| (*addr, 1)).collect();
+    let mut service = service(router(r#""round_robin""#, &pool), [127, 0, 0, 1]);
+
+    let answers = distribution(&mut service, 9).await;
+    assert_eq!(answers["a"], 3);
+    assert_eq!(answers["b"], 3);
+    assert_eq!(answers["c"], 3);
+}
+
+#[tokio::test]
+async fn weighted_follows_backend_weights() {
+    let backends = three_backends().await;
+    let pool = vec![(backends[0], 3), (backends[1], 1), (backends[2], 0)];
+    let mut service = service(router(r#""weighted""#, &pool), [127, 0, 0, 1]);
+
+    let answers = distribution(&mut service, 8).await;
+    assert_eq!(answers["a"], 6);
+    assert_eq!(answers["b"], 2);
+    assert!(!answers.contains_key("c"));
+}
+
+#[tokio::test]
+async fn least_connections_avoids_busy_backends() {
+    let slow = spawn_backend("slow", Duration::from_millis(300));
+    let fast = spawn_backend("fast", Duration::from_millis(0));
+    let router = router(r#""least_connections""#, &[(slow, 1), (fast, 1)]);
+
+    // Both backends are idle, the first one gets the request and keeps it in flight
+    let mut first = service(router.clone(), [127, 0, 0, 1]);
+    let in_flight = tokio::spawn(async move { send(&mut first, None).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut service = service(router, [127, 0, 0, 1]);
+    let answers = distribution(&mut service, 4).await;
+    assert_eq!(answers["fast"], 4);
+
+    assert_eq!(in_flight.await.unwrap(), "slow");
+}
+
+#[tokio::test]
+async fn least_connections_counts_streaming_responses_until_their_body_ends() {
+    let streaming = spawn_streaming_backend("streaming", Duration::from_millis(300));
+    let fast = spawn_backend("fast", Duration::from_millis(0));
+    let router = router(r#""least_connections""#, &[(streaming, 1), (fast, 1)]);
+
+    // The response headers came back, its body is still being sent
+    let mut first = service(router.clone(), [127, 0, 0, 1]);
+    let req = Request::get("/")
+        .header("host", "proxy")
+        .body(Body::empty())
+        .unwrap();
+    let mut streamed = common::call(&mut first, req).await.unwrap().into_body();
+    assert_eq!(streamed.data().await.unwrap().unwrap(), "streaming");
+
+    let mut service = service(router, [127, 0, 0, 1]);
+    let answers = distribution(&mut service, 4).await;
+    assert_eq!(answers["fast"], 4);
+
+    assert_eq!(streamed.data().await.unwrap().unwrap(), " done");
+}
+
+#[tokio::test]
+async fn random_two_choices_uses_every_backend() {
+    let backends = three_backends().await;
+    let pool: Vec<_> = backends.iter().map(|addr| (*addr, 1)).collect();
+    let mut service = service(router(r#""random_two_choices""#, &pool), [127, 0, 0, 1]);
+
+    let answers = distribution(&mut service, 60).await;
+    assert_eq!(answers.len(), 3);
+}
+
+#[tokio::test]
+async fn consistent_hash_sticks_to_a_backend() {
+    let backends = three_backends().await;
+    let pool: Vec<_> = backends.iter().map(|addr| (*addr, 1)).collect();
+
+    // On a header
+    let router_on_header = router(r#"{ "consistent_hash": { "header": "x-user" } }"#, &pool);
+    let mut service_on_header = service(router_on_header, [127, 0, 0, 1]);
+    let mut backends_used = HashMap::new();
+    for user in 0..30 {
+        let user = format!("user-{}", user);
+        let first = send(&mut service_on_header, Some(&user)).await;
+        for _ in 0..3 {
+            assert_eq!(send(&mut service_on_header, Some(&user)).await, first);
+        }
+        *backends_used.entry(first).or_insert(0) += 1;
+    }
+    assert_eq!(backends_used.len(), 3);
+
+    // On the client IP
+    let router_on_ip = router(r#"{ "consistent_hash": {} }"#, &pool);
+    let answers = join_all((1..=30).map(|ip| {
+        let mut service = service(router_on_ip.clone(), [10, 0, 0, ip]);
+        async move {
+            let first = send(&mut service, None).await;
+            assert_eq!(send(&mut service, None).await, first);
+            first
+        }
+    }))
+    .await;
+    let backends_used: HashSet<String> = answers.into_iter().collect();
+    assert_eq!(backends_used.len(), 3);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use futures::future::join_all;
+use hyper::{Body, Request, Response};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Locked, MiddlewareMut, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::sync::Arc;
+
+/// Counts requests with a plain field, answering each with its number
+struct Counter {
+    count: usize,
+}
+
+impl MiddlewareMut for Counter {
+    fn name() -> String {
+        String::from("Counter")
+    }
+
+    fn before_request(
+        &mut self,
+        _req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.count += 1;
+        Ok(MiddlewareResult::RespondWith(Response::new(Body::from(
+            self.count.to_string(),
+        ))))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn locked_middlewares_mutate_their_state_across_concurrent_requests() {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> =
+        vec![Box::new(Locked::new(Counter { count: 0 }))];
+    let middlewares = Arc::new(middlewares);
+
+    // One service per connection, every connection shares the middleware
+    let requests = (0..50).map(|_| {
+        let mut service =
+            ProxyService::new(Arc::clone(&middlewares), ([127, 0, 0, 1], 4242).into());
+        tokio::spawn(async move {
+            let req = Request::get("/").body(Body::empty()).unwrap();
+            common::send(&mut service, req)
+                .await
+                .1
+                .parse::<usize>()
+                .unwrap()
+        })
+    });
+    let mut counts: Vec<usize> = join_all(requests)
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+
+    // Every request saw its own increment, none was lost
+    counts.sort_unstable();
+    assert_eq!(counts, (1..=50).collect::<Vec<_>>());
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Forward;
+use hyper::{Body, Client, Response};
+use simple_proxy::proxy::client::PoolConfig;
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counts the connections it accepted
+fn spawn_upstream(connections: Arc<AtomicUsize>) -> SocketAddr {
+    common::spawn_counting_upstream(connections, |_req| async {
+        Response::new(Body::from("upstream"))
+    })
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Sends a request from `clients` separate client connections, returns the upstream connections made
+async fn upstream_connections(pool: Option<PoolConfig>, clients: usize) -> usize {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let upstream = spawn_upstream(Arc::clone(&connections));
+    let addr = free_addr();
+
+    let mut proxy = SimpleProxy::bind(addr, Environment::Development);
+    proxy.add_middleware(Box::new(Forward(upstream)));
+    if let Some(pool) = pool {
+        proxy.set_pool_config(pool);
+    }
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for _ in 0..clients {
+        // A new client opens a new connection to the proxy
+        let res = Client::new()
+            .get(format!("http://{}/", addr).parse().unwrap())
+            .await
+            .unwrap();
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+    }
+
+    connections.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn client_connections_share_upstream_connections() {
+    assert_eq!(upstream_connections(None, 3).await, 1);
+}
+
+#[tokio::test]
+async fn idle_upstream_connections_can_be_disabled() {
+    let pool = PoolConfig {
+        max_idle_per_host: 0,
+        ..PoolConfig::default()
+    };
+    assert_eq!(upstream_connections(Some(pool), 3).await, 3);
+}
@@ -0,0 +1,149 @@
+use hyper::{Body, Request, Response};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use simple_proxy::proxy_protocol::{read_header, ProxyHeader, ProxyHeaderError};
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: proxy\r\nConnection: close\r\n\r\n";
+
+/// Answers with the client address
+struct Answer;
+
+impl Middleware for Answer {
+    fn name() -> String {
+        String::from("Answer")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(MiddlewareResult::RespondWith(Response::new(Body::from(
+            ctx.remote_addr.to_string(),
+        ))))
+    }
+}
+
+async fn spawn_proxy() -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut proxy = SimpleProxy::bind(addr, Environment::Development);
+    proxy.set_proxy_protocol(true);
+    proxy.add_middleware(Box::new(Answer));
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    addr
+}
+
+/// Sends `header` then a request, returns the response body, empty when the connection is closed
+async fn exchange(proxy: SocketAddr, header: &[u8]) -> String {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    stream.write_all(header).await.unwrap();
+    stream.write_all(REQUEST).await.unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await;
+    response
+        .split("\r\n\r\n")
+        .nth(1)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.push(0x20 | command);
+    header.push(family);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend_from_slice(addresses);
+    header
+}
+
+#[tokio::test]
+async fn v1_header_gives_the_client_address() {
+    let proxy = spawn_proxy().await;
+
+    let body = exchange(proxy, b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\n").await;
+    assert_eq!(body, "203.0.113.7:51234");
+
+    let body = exchange(proxy, b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n").await;
+    assert_eq!(body, "[2001:db8::7]:51234");
+}
+
+#[tokio::test]
+async fn v2_header_gives_the_client_address() {
+    let proxy = spawn_proxy().await;
+
+    // TCP over IPv4, followed by a TLV the proxy skips
+    let mut addresses = vec![198, 51, 100, 9, 10, 0, 0, 1, 0xc8, 0x1c, 0, 80];
+    addresses.extend_from_slice(&[0x04, 0, 2, b'o', b'k']);
+    let body = exchange(proxy, &v2_header(1, 0x11, &addresses)).await;
+    assert_eq!(body, "198.51.100.9:51228");
+
+    let mut addresses = vec![0; 36];
+    addresses[0..2].copy_from_slice(&[0x20, 0x01]);
+    addresses[15] = 9;
+    addresses[32..34].copy_from_slice(&4242u16.to_be_bytes());
+    let body = exchange(proxy, &v2_header(1, 0x21, &addresses)).await;
+    assert_eq!(body, "[2001::9]:4242");
+}
+
+#[tokio::test]
+async fn local_and_unknown_headers_keep_the_peer_address() {
+    let proxy = spawn_proxy().await;
+
+    let body = exchange(proxy, b"PROXY UNKNOWN\r\n").await;
+    assert!(body.starts_with("127.0.0.1:"), "{}", body);
+
+    let body = exchange(proxy, &v2_header(0, 0x00, &[])).await;
+    assert!(body.starts_with("127.0.0.1:"), "{}", body);
+}
+
+#[tokio::test]
+async fn connections_without_a_valid_header_are_closed() {
+    let proxy = spawn_proxy().await;
+
+    assert_eq!(exchange(proxy, b"").await, "");
+    assert_eq!(
+        exchange(proxy, b"PROXY TCP4 nope 10.0.0.1 1 2\r\n").await,
+        ""
+    );
+    assert_eq!(
+        exchange(proxy, b"PROXY TCP4 2001:db8::7 10.0.0.1 1 2\r\n").await,
+        ""
+    );
+}
+
+#[tokio::test]
+async fn read_header_leaves_the_request_unread() {
+    let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\nGET /";
+    let header = read_header(&mut input).await.unwrap();
+    assert_eq!(
+        header,
+        ProxyHeader {
+            source: Some("203.0.113.7:51234".parse().unwrap()),
+            destination: Some("10.0.0.1:80".parse().unwrap()),
+        }
+    );
+    assert_eq!(input, b"GET /");
+
+    let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80 and too much text to fit in a v1 header which is at most 107 bytes\r\n";
+    assert!(matches!(
+        read_header(&mut input).await,
+        Err(ProxyHeaderError::Invalid(_))
+    ));
+
+    let mut input: &[u8] = REQUEST;
+    assert!(matches!(
+        read_header(&mut input).await,
+        Err(ProxyHeaderError::MissingHeader)
+    ));
+}
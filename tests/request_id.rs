@@ -0,0 +1,142 @@
+mod common;
+
+use common::Forward;
+use hyper::header::HeaderName;
+use hyper::{Body, Request, Response, StatusCode};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Stub upstream answering the request ID header it received
+fn spawn_upstream(header: &'static str) -> SocketAddr {
+    common::spawn_upstream(move |req| async move {
+        let id = req
+            .headers()
+            .get(header)
+            .map(|id| id.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        Response::new(Body::from(id))
+    })
+}
+
+/// Records the request IDs it saw in the context, answers right away when `respond` is set
+struct Seen {
+    ids: Arc<Mutex<Vec<String>>>,
+    respond: bool,
+}
+
+impl Middleware for Seen {
+    fn name() -> String {
+        String::from("Seen")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        self.ids.lock().unwrap().push(ctx.req_id.clone());
+        if self.respond {
+            return Ok(MiddlewareResult::RespondWith(
+                Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::empty())?,
+            ));
+        }
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+/// Sends requests to the upstream, or answers them right away without one
+fn service(upstream: Option<SocketAddr>) -> (ProxyService, Arc<Mutex<Vec<String>>>) {
+    let ids = Arc::new(Mutex::new(vec![]));
+    let seen = Seen {
+        ids: Arc::clone(&ids),
+        respond: upstream.is_none(),
+    };
+    let mut middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(seen)];
+    if let Some(upstream) = upstream {
+        middlewares.push(Box::new(Forward(upstream)));
+    }
+    (common::service(middlewares), ids)
+}
+
+/// The response and its body
+async fn send(service: &mut ProxyService, req: http::request::Builder) -> (Response<Body>, String) {
+    common::send(service, req.body(Body::empty()).unwrap()).await
+}
+
+fn is_uuid_v4(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.iter().map(|group| group.len()).collect::<Vec<_>>() == [8, 4, 4, 4, 12]
+        && id.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+        && groups[2].starts_with('4')
+}
+
+#[tokio::test]
+async fn generated_ids_are_forwarded_and_echoed() {
+    let upstream = spawn_upstream("x-request-id");
+    let (mut service, seen) = service(Some(upstream));
+
+    let (res, forwarded) = send(&mut service, Request::get("/")).await;
+    let (_, other) = send(&mut service, Request::get("/")).await;
+
+    assert!(is_uuid_v4(&forwarded), "{}", forwarded);
+    assert_ne!(forwarded, other);
+    assert_eq!(res.headers()["x-request-id"], forwarded.as_str());
+    assert_eq!(*seen.lock().unwrap(), vec![forwarded, other]);
+}
+
+#[tokio::test]
+async fn incoming_ids_are_honored() {
+    let upstream = spawn_upstream("x-request-id");
+    let (mut service, seen) = service(Some(upstream));
+
+    let req = Request::get("/").header("X-Request-Id", "abc-123");
+    let (res, forwarded) = send(&mut service, req).await;
+
+    assert_eq!(forwarded, "abc-123");
+    assert_eq!(res.headers()["x-request-id"], "abc-123");
+    assert_eq!(*seen.lock().unwrap(), vec!["abc-123"]);
+}
+
+#[tokio::test]
+async fn invalid_incoming_ids_are_replaced() {
+    let upstream = spawn_upstream("x-request-id");
+    let (mut service, _) = service(Some(upstream));
+
+    for id in ["", "with spaces", &"a".repeat(129)] {
+        let req = Request::get("/").header("X-Request-Id", id);
+        let (_, forwarded) = send(&mut service, req).await;
+        assert!(is_uuid_v4(&forwarded), "{:?} became {}", id, forwarded);
+    }
+}
+
+#[tokio::test]
+async fn header_name_is_configurable() {
+    let upstream = spawn_upstream("x-correlation-id");
+    let (mut service, _) = service(Some(upstream));
+    service.set_request_id_header(HeaderName::from_static("x-correlation-id"));
+
+    let req = Request::get("/")
+        .header("X-Correlation-Id", "corr-1")
+        .header("X-Request-Id", "ignored");
+    let (res, forwarded) = send(&mut service, req).await;
+
+    assert_eq!(forwarded, "corr-1");
+    assert_eq!(res.headers()["x-correlation-id"], "corr-1");
+}
+
+#[tokio::test]
+async fn early_responses_echo_the_id() {
+    let (mut service, seen) = service(None);
+
+    let (res, _) = send(&mut service, Request::get("/")).await;
+
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let id = res.headers()["x-request-id"].to_str().unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![id]);
+}
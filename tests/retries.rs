@@ -0,0 +1,194 @@
+mod common;
+
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::retry::{RetryBudget, RetryBudgetConfig, RetryPolicy};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// Answers 503 to the first `failures` requests, then echoes the body.
+/// Bodies received are recorded in `bodies`.
+fn spawn_upstream(failures: usize, bodies: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    serve(listener, failures, bodies)
+}
+
+fn serve(
+    listener: std::net::TcpListener,
+    failures: usize,
+    bodies: Arc<Mutex<Vec<String>>>,
+) -> SocketAddr {
+    common::spawn_upstream_on(listener, move |req: Request<Body>| {
+        let bodies = Arc::clone(&bodies);
+        async move {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let mut bodies = bodies.lock().unwrap();
+            bodies.push(body.clone());
+
+            let mut res = Response::new(Body::from(body));
+            if bodies.len() <= failures {
+                *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+            res
+        }
+    })
+}
+
+/// Sends every request to the upstream with the given retry policy
+struct Retrying {
+    upstream: SocketAddr,
+    policy: RetryPolicy,
+}
+
+impl Middleware for Retrying {
+    fn name() -> String {
+        String::from("Retrying")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        *req.uri_mut() = format!("http://{}/", self.upstream).parse::<Uri>()?;
+        state.insert(self.policy.clone());
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+fn service(upstream: SocketAddr, policy: RetryPolicy) -> ProxyService {
+    common::service(vec![Box::new(Retrying { upstream, policy })])
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        backoff_ms: 1,
+        ..RetryPolicy::default()
+    }
+}
+
+async fn send(service: &mut ProxyService, method: Method, body: &'static str) -> StatusCode {
+    let req = Request::builder()
+        .method(method)
+        .uri("/")
+        .header("content-length", body.len())
+        .body(Body::from(body))
+        .unwrap();
+    common::call(service, req).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn retries_idempotent_requests_until_success() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(2, Arc::clone(&bodies));
+    let mut service = service(upstream, policy());
+
+    assert_eq!(send(&mut service, Method::GET, "").await, StatusCode::OK);
+    assert_eq!(bodies.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn stops_after_max_attempts() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(10, Arc::clone(&bodies));
+    let mut service = service(
+        upstream,
+        RetryPolicy {
+            max_attempts: 2,
+            ..policy()
+        },
+    );
+
+    assert_eq!(
+        send(&mut service, Method::GET, "").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(bodies.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn only_retries_post_when_allowed_and_replays_its_body() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(1, Arc::clone(&bodies));
+    let mut default = service(upstream, policy());
+
+    assert_eq!(
+        send(&mut default, Method::POST, "payload").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(bodies.lock().unwrap().len(), 1);
+
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(1, Arc::clone(&bodies));
+    let mut allowed = service(
+        upstream,
+        RetryPolicy {
+            non_idempotent: true,
+            ..policy()
+        },
+    );
+
+    assert_eq!(
+        send(&mut allowed, Method::POST, "payload").await,
+        StatusCode::OK
+    );
+    assert_eq!(*bodies.lock().unwrap(), vec!["payload", "payload"]);
+}
+
+#[tokio::test]
+async fn does_not_retry_bodies_over_the_limit() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(1, Arc::clone(&bodies));
+    let mut service = service(
+        upstream,
+        RetryPolicy {
+            max_body_bytes: 4,
+            ..policy()
+        },
+    );
+
+    assert_eq!(
+        send(&mut service, Method::PUT, "payload").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(bodies.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn retries_broken_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let served = Arc::clone(&bodies);
+    tokio::spawn(async move {
+        // The first connection is closed without an answer
+        drop(listener.accept().await.unwrap());
+        serve(listener.into_std().unwrap(), 0, served);
+    });
+    let mut service = service(upstream, policy());
+
+    assert_eq!(send(&mut service, Method::GET, "").await, StatusCode::OK);
+    assert_eq!(bodies.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn retry_budget_limits_retries() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let upstream = spawn_upstream(10, Arc::clone(&bodies));
+    let mut service = service(upstream, policy());
+    service.set_retry_budget(Arc::new(RetryBudget::new(RetryBudgetConfig {
+        ratio: 0.0,
+        max_retries: 1.0,
+    })));
+
+    send(&mut service, Method::GET, "").await;
+    assert_eq!(bodies.lock().unwrap().len(), 2);
+
+    send(&mut service, Method::GET, "").await;
+    assert_eq!(bodies.lock().unwrap().len(), 3);
+}
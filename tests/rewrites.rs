@@ -0,0 +1,147 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::router::{Router, RouterConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Stub upstream answering the path and query it received
+fn spawn_upstream() -> SocketAddr {
+    common::spawn_upstream(|req| async move {
+        let path = req.uri().path_and_query().unwrap().to_string();
+        Response::new(Body::from(path))
+    })
+}
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// A service routing everything through the `rewrite` actions
+async fn service(rewrite: &str) -> ProxyService {
+    let upstream = spawn_upstream();
+    let config = format!(
+        r#"{{
+            "rules": [{{
+                "from": {{ "host": ".*", "path": "(.*)" }},
+                "to": {{ "host": "{}", "path": "$1" }},
+                "public": true,
+                "rewrite": {}
+            }}]
+        }}"#,
+        upstream, rewrite
+    );
+
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_rewrites_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+    common::service(middlewares)
+}
+
+/// Path and query the upstream received for `path`
+async fn upstream_path(service: &mut ProxyService, path: &str) -> String {
+    let req = Request::get(path).header("host", "proxy");
+    common::send(service, req.body(Body::empty()).unwrap())
+        .await
+        .1
+}
+
+#[tokio::test]
+async fn strip_prefix_keeps_the_query() {
+    let mut service = service(r#"[{ "strip_prefix": "/api/v1" }]"#).await;
+
+    let stripped = upstream_path(&mut service, "/api/v1/users?id=1&name=a%20b").await;
+    assert_eq!(stripped, "/users?id=1&name=a%20b");
+    assert_eq!(upstream_path(&mut service, "/api/v1?x=1").await, "/?x=1");
+    assert_eq!(upstream_path(&mut service, "/api/v1").await, "/");
+    // Not on a segment boundary
+    assert_eq!(
+        upstream_path(&mut service, "/api/v10/users").await,
+        "/api/v10/users"
+    );
+}
+
+#[tokio::test]
+async fn add_prefix_keeps_the_query() {
+    let mut service = service(r#"[{ "add_prefix": "/internal/" }]"#).await;
+
+    assert_eq!(
+        upstream_path(&mut service, "/users?page=2").await,
+        "/internal/users?page=2"
+    );
+}
+
+#[tokio::test]
+async fn replace_uses_named_captures() {
+    let mut service = service(
+        r#"[
+            { "replace": { "regex": "^/users/(?P<id>[0-9]+)$", "with": "/profiles/$id" } },
+            { "replace": { "regex": "-", "with": "_", "all": true } }
+        ]"#,
+    )
+    .await;
+
+    assert_eq!(
+        upstream_path(&mut service, "/users/42?full=true").await,
+        "/profiles/42?full=true"
+    );
+    assert_eq!(
+        upstream_path(&mut service, "/a-b-c?x=a-b").await,
+        "/a_b_c?x=a-b"
+    );
+}
+
+#[tokio::test]
+async fn query_params_are_set_and_removed() {
+    let mut service = service(
+        r#"[
+            { "remove_query": ["token"] },
+            { "set_query": { "source": "proxy", "name": "jane doe" } }
+        ]"#,
+    )
+    .await;
+
+    assert_eq!(
+        upstream_path(&mut service, "/search?q=a%2Bb&token=secret&name=x&page=2").await,
+        "/search?q=a%2Bb&page=2&name=jane%20doe&source=proxy"
+    );
+    assert_eq!(
+        upstream_path(&mut service, "/search").await,
+        "/search?name=jane%20doe&source=proxy"
+    );
+}
+
+#[tokio::test]
+async fn prefixes_and_query_combine() {
+    let mut service = service(
+        r#"[
+            { "strip_prefix": "/public" },
+            { "add_prefix": "/v2" },
+            { "remove_query": ["debug"] }
+        ]"#,
+    )
+    .await;
+
+    assert_eq!(
+        upstream_path(&mut service, "/public/items?debug=1&sort=asc").await,
+        "/v2/items?sort=asc"
+    );
+    assert_eq!(upstream_path(&mut service, "/public?debug").await, "/v2/");
+}
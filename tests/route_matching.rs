@@ -0,0 +1,152 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::router::{Router, RouterConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use simple_proxy::proxy::service::ProxyService;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Stub upstream answering its name
+fn spawn_upstream(name: &'static str) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move { Response::new(Body::from(name)) })
+}
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// A router whose rules are (extra `from` fields, upstream) pairs, each upstream answering its name
+async fn router(rules: &[(&str, &'static str)]) -> Router {
+    let mut json = vec![];
+    for (conditions, name) in rules {
+        let upstream = spawn_upstream(name);
+        json.push(format!(
+            r#"{{
+                "from": {{ "host": ".*", "path": "(.*)"{} }},
+                "to": {{ "host": "{}", "path": "$1" }},
+                "public": true
+            }}"#,
+            conditions, upstream
+        ));
+    }
+    let config = format!(r#"{{ "rules": [{}] }}"#, json.join(","));
+
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_matching_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+    router
+}
+
+fn service(router: &Router, client_ip: [u8; 4], port: u16) -> ProxyService {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router.clone())];
+    let mut service = common::service_from((client_ip, 4242).into(), middlewares);
+    service.set_local_addr(([127, 0, 0, 1], port).into());
+    service
+}
+
+async fn send(service: &mut ProxyService, req: http::request::Builder) -> String {
+    let req = req.header("host", "proxy").body(Body::empty()).unwrap();
+    common::send(service, req).await.1
+}
+
+#[tokio::test]
+async fn matches_methods_and_headers() {
+    let router = router(&[
+        (
+            r#", "methods": ["POST"], "headers": { "X-Api-Version": "2" }"#,
+            "v2",
+        ),
+        ("", "v1"),
+    ])
+    .await;
+    let mut service = service(&router, [127, 0, 0, 1], 80);
+
+    let v2 = Request::post("/api").header("X-Api-Version", "2");
+    assert_eq!(send(&mut service, v2).await, "v2");
+    let get = Request::get("/api").header("x-api-version", "2");
+    assert_eq!(send(&mut service, get).await, "v1");
+    let v1 = Request::post("/api").header("X-Api-Version", "1");
+    assert_eq!(send(&mut service, v1).await, "v1");
+    assert_eq!(send(&mut service, Request::post("/api")).await, "v1");
+}
+
+#[tokio::test]
+async fn matches_header_regexes_and_presence() {
+    let router = router(&[
+        (
+            r#", "headers": { "User-Agent": { "regex": "^curl/" } }"#,
+            "curl",
+        ),
+        (
+            r#", "headers": { "Cookie": { "present": false } }"#,
+            "anonymous",
+        ),
+        ("", "other"),
+    ])
+    .await;
+    let mut service = service(&router, [127, 0, 0, 1], 80);
+
+    let curl = Request::get("/").header("User-Agent", "curl/8.0");
+    assert_eq!(send(&mut service, curl).await, "curl");
+    assert_eq!(send(&mut service, Request::get("/")).await, "anonymous");
+    let session = Request::get("/").header("Cookie", "session=1");
+    assert_eq!(send(&mut service, session).await, "other");
+}
+
+#[tokio::test]
+async fn matches_query_params() {
+    let router = router(&[
+        (
+            r#", "query": { "user": "jane doe", "beta": { "present": true } }"#,
+            "beta",
+        ),
+        ("", "stable"),
+    ])
+    .await;
+    let mut service = service(&router, [127, 0, 0, 1], 80);
+
+    let beta = Request::get("/search?user=jane%20doe&beta");
+    assert_eq!(send(&mut service, beta).await, "beta");
+    let plus = Request::get("/search?beta=1&user=jane+doe");
+    assert_eq!(send(&mut service, plus).await, "beta");
+    let other = Request::get("/search?user=john&beta");
+    assert_eq!(send(&mut service, other).await, "stable");
+    let missing = Request::get("/search?user=jane%20doe");
+    assert_eq!(send(&mut service, missing).await, "stable");
+}
+
+#[tokio::test]
+async fn matches_client_ips_and_listener_ports() {
+    let router = router(&[
+        (
+            r#", "client_ips": ["10.0.0.0/8", "192.168.1.7"]"#,
+            "internal",
+        ),
+        (r#", "ports": [8443]"#, "admin"),
+        ("", "public"),
+    ])
+    .await;
+
+    let mut internal = service(&router, [10, 1, 2, 3], 80);
+    assert_eq!(send(&mut internal, Request::get("/")).await, "internal");
+    let mut host = service(&router, [192, 168, 1, 7], 80);
+    assert_eq!(send(&mut host, Request::get("/")).await, "internal");
+    let mut admin = service(&router, [192, 168, 1, 8], 8443);
+    assert_eq!(send(&mut admin, Request::get("/")).await, "admin");
+    let mut public = service(&router, [11, 0, 0, 1], 80);
+    assert_eq!(send(&mut public, Request::get("/")).await, "public");
+}
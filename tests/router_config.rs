@@ -0,0 +1,173 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use hyper::{Body, Request, StatusCode};
+use simple_proxy::middlewares::router::{Router, RouterConfig, RouterError};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Loads a router from the `config` file contents
+fn try_router(config: &str) -> Result<Router, RouterError> {
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_config_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::try_new(&Config(path.to_str().unwrap().to_string()));
+    std::fs::remove_file(&path).unwrap();
+    router
+}
+
+fn rule(host: &str, path: &str) -> String {
+    format!(
+        r#"{{ "from": {{ "host": "{}", "path": "{}" }}, "to": {{ "host": "upstream", "path": "$1" }}, "public": true }}"#,
+        host, path
+    )
+}
+
+fn rules(rules: &[String]) -> String {
+    format!(r#"{{ "rules": [{}] }}"#, rules.join(","))
+}
+
+#[test]
+fn valid_config_loads() {
+    let config = rules(&[rule("api.example.com", "(.*)"), rule(".*", "(.*)")]);
+    assert!(try_router(&config).is_ok());
+}
+
+#[test]
+fn missing_file_is_reported() {
+    let err = Router::try_new(&Config(String::from("/nonexistent/rules.json"))).unwrap_err();
+    assert!(matches!(err, RouterError::Io { .. }), "{}", err);
+    assert_eq!(err.path(), "/nonexistent/rules.json");
+}
+
+#[test]
+fn syntax_errors_have_a_position() {
+    let err = try_router("{\n  \"rules\": [\n    { \"from\": }\n  ]\n}").unwrap_err();
+    match err {
+        RouterError::Parse { line, column, .. } => assert_eq!((line, column), (3, 15)),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn invalid_rules_have_an_index() {
+    let config = rules(&[
+        rule("a.example.com", "(.*)"),
+        String::from(
+            r#"{ "from": { "host": ".*", "path": ".*" }, "to": { "host": "x", "path": "/" } }"#,
+        ),
+    ]);
+    let err = try_router(&config).unwrap_err();
+    assert!(matches!(err, RouterError::InvalidRule { .. }), "{}", err);
+    assert_eq!(err.rule_index(), Some(1));
+    assert!(err.to_string().contains("public"), "{}", err);
+}
+
+#[test]
+fn bad_regexes_are_reported() {
+    let config = rules(&[rule("a.example.com", "(.*)"), rule(".*", "/api/(.*")]);
+    match try_router(&config).unwrap_err() {
+        RouterError::InvalidRegex {
+            index,
+            field,
+            pattern,
+            ..
+        } => {
+            assert_eq!(index, 1);
+            assert_eq!(field, "from.path");
+            assert_eq!(pattern, "/api/(.*");
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn unknown_upstreams_are_reported() {
+    let config = r#"{ "rules": [{ "from": { "host": ".*", "path": ".*" }, "to": { "host": "x", "path": "/" }, "public": true, "upstream": "missing" }] }"#;
+    match try_router(config).unwrap_err() {
+        RouterError::UnknownUpstream {
+            index, upstream, ..
+        } => {
+            assert_eq!(index, 0);
+            assert_eq!(upstream, "missing");
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn shadowed_rules_are_reported() {
+    let config = rules(&[
+        rule("a.example.com", "(.*)"),
+        rule(".*", "^(.*)$"),
+        rule("b.example.com", "/api/(.*)"),
+    ]);
+    match try_router(&config).unwrap_err() {
+        RouterError::ShadowedRule {
+            index, shadowed_by, ..
+        } => assert_eq!((index, shadowed_by), (2, 1)),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    let config = rules(&[rule("a.example.com", "(.*)"), rule("a.example.com", "(.*)")]);
+    let err = try_router(&config).unwrap_err();
+    assert!(matches!(err, RouterError::ShadowedRule { .. }), "{}", err);
+    assert_eq!(err.rule_index(), Some(1));
+}
+
+#[tokio::test]
+async fn requests_without_host_are_rejected() {
+    let router = try_router(&rules(&[rule(".*", "(.*)")])).unwrap();
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+    let mut service = common::service(middlewares);
+
+    let res = common::call(&mut service, Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn targets_that_are_not_header_values_are_errors() {
+    let rule = r#"{ "from": { "host": ".*", "path": "(.*)" }, "to": { "host": "up\u007fstream", "path": "$1" }, "public": true }"#;
+    let router = try_router(&rules(&[rule.to_string()])).unwrap();
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router)];
+    let mut service = common::service(middlewares);
+
+    let req = Request::get("/")
+        .header("host", "proxy")
+        .body(Body::empty())
+        .unwrap();
+    let res = common::call(&mut service, req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[cfg(not(feature = "yaml"))]
+#[test]
+fn disabled_formats_are_reported() {
+    let path =
+        std::env::temp_dir().join(format!("simple_proxy_config_{}.yaml", std::process::id()));
+    std::fs::write(&path, "rules: []").unwrap();
+    let err = Router::try_new(&Config(path.to_str().unwrap().to_string())).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        matches!(err, RouterError::UnsupportedFormat { .. }),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("`yaml` feature"), "{}", err);
+}
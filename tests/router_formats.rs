@@ -0,0 +1,180 @@
+#![cfg(all(feature = "yaml", feature = "toml"))]
+
+use simple_proxy::middlewares::router::{Router, RouterConfig, RouterError, RouterFormat};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Config(String, Option<RouterFormat>);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+
+    fn get_router_format(&self) -> Option<RouterFormat> {
+        self.1
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Loads a router from `config` written to a file with the `extension`
+fn try_router(
+    config: &str,
+    extension: &str,
+    format: Option<RouterFormat>,
+) -> Result<Router, RouterError> {
+    let path = std::env::temp_dir().join(format!(
+        "simple_proxy_formats_{}_{}.{}",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed),
+        extension
+    ));
+    std::fs::write(&path, config).unwrap();
+    let router = Router::try_new(&Config(path.to_str().unwrap().to_string(), format));
+    std::fs::remove_file(&path).unwrap();
+    router
+}
+
+fn rules(config: &str, extension: &str) -> String {
+    let router = try_router(config, extension, None).unwrap();
+    format!("{:?}", router.rules())
+}
+
+const JSON: &str = r#"{
+    "rules": [
+        {
+            "from": { "host": "api\\.example\\.com", "path": "^/v1/(.*)$" },
+            "to": { "host": "", "path": "/$1" },
+            "public": false,
+            "upstream": "api",
+            "retry": { "max_attempts": 2 },
+            "timeouts": { "connect_ms": 500 }
+        },
+        {
+            "from": { "host": ".*", "path": "(.*)" },
+            "to": { "host": "grpc.internal:50051", "path": "$1" },
+            "public": true,
+            "http2": true
+        }
+    ],
+    "upstreams": {
+        "api": {
+            "strategy": { "consistent_hash": { "header": "X-User-Id" } },
+            "backends": [
+                { "host": "10.0.0.1:8080", "weight": 2 },
+                { "host": "10.0.0.2:8080" }
+            ],
+            "health_check": { "probe": { "http": { "path": "/health" } } }
+        }
+    }
+}"#;
+
+const YAML: &str = r#"
+rules:
+  - from: { host: 'api\.example\.com', path: '^/v1/(.*)$' }
+    to: { host: "", path: /$1 }
+    public: false
+    upstream: api
+    retry: { max_attempts: 2 }
+    timeouts: { connect_ms: 500 }
+  - from: { host: .*, path: (.*) }
+    to: { host: "grpc.internal:50051", path: $1 }
+    public: true
+    http2: true
+upstreams:
+  api:
+    strategy:
+      consistent_hash: { header: X-User-Id }
+    backends:
+      - { host: "10.0.0.1:8080", weight: 2 }
+      - host: "10.0.0.2:8080"
+    health_check:
+      probe:
+        http: { path: /health }
+"#;
+
+const TOML: &str = r#"
+[[rules]]
+from = { host = 'api\.example\.com', path = '^/v1/(.*)$' }
+to = { host = "", path = "/$1" }
+public = false
+upstream = "api"
+retry = { max_attempts = 2 }
+timeouts = { connect_ms = 500 }
+
+[[rules]]
+from = { host = ".*", path = "(.*)" }
+to = { host = "grpc.internal:50051", path = "$1" }
+public = true
+http2 = true
+
+[upstreams.api]
+strategy = { consistent_hash = { header = "X-User-Id" } }
+backends = [
+    { host = "10.0.0.1:8080", weight = 2 },
+    { host = "10.0.0.2:8080" },
+]
+health_check = { probe = { http = { path = "/health" } } }
+"#;
+
+#[test]
+fn all_formats_give_the_same_rules() {
+    let json = rules(JSON, "json");
+    assert_eq!(rules(YAML, "yaml"), json);
+    assert_eq!(rules(YAML, "yml"), json);
+    assert_eq!(rules(TOML, "toml"), json);
+}
+
+#[test]
+fn json_round_trips_through_yaml_and_toml() {
+    let value: serde_json::Value = serde_json::from_str(JSON).unwrap();
+    let json = rules(JSON, "json");
+
+    let yaml = serde_yaml::to_string(&value).unwrap();
+    assert_eq!(rules(&yaml, "yaml"), json);
+    let toml = toml::to_string(&value).unwrap();
+    assert_eq!(rules(&toml, "toml"), json);
+}
+
+#[test]
+fn explicit_format_overrides_the_extension() {
+    let json = rules(JSON, "json");
+    let router = try_router(YAML, "conf", Some(RouterFormat::Yaml)).unwrap();
+    assert_eq!(format!("{:?}", router.rules()), json);
+
+    let err = try_router(TOML, "conf", None).unwrap_err();
+    assert!(matches!(err, RouterError::Parse { .. }), "{}", err);
+}
+
+#[test]
+fn syntax_errors_have_a_position() {
+    let yaml = "rules:\n  - from: { host: .*\n";
+    match try_router(yaml, "yaml", None).unwrap_err() {
+        RouterError::Parse { line, .. } => assert_eq!(line, 3),
+        err => panic!("unexpected error: {}", err),
+    }
+
+    let toml = "[[rules]]\npublic = true\nfrom = {\n";
+    match try_router(toml, "toml", None).unwrap_err() {
+        RouterError::Parse { line, column, .. } => assert_eq!((line, column), (3, 9)),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn rules_are_validated_the_same_way() {
+    let toml = r#"
+[[rules]]
+from = { host = ".*", path = "(.*)" }
+to = { host = "a", path = "$1" }
+public = true
+
+[[rules]]
+from = { host = "b.example.com", path = "/api(" }
+to = { host = "b", path = "$1" }
+public = true
+"#;
+    let err = try_router(toml, "toml", None).unwrap_err();
+    assert!(matches!(err, RouterError::InvalidRegex { .. }), "{}", err);
+    assert_eq!(err.rule_index(), Some(1));
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "router")]
+
+mod common;
+
+use hyper::{Body, Request, Response};
+use simple_proxy::middlewares::router::{Router, RouterConfig};
+use simple_proxy::proxy::middleware::AsyncMiddleware;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Stub upstream answering its name
+fn spawn_upstream(name: &'static str) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move { Response::new(Body::from(name)) })
+}
+
+struct Config(String);
+
+impl RouterConfig for Config {
+    fn get_router_filename(&self) -> &str {
+        &self.0
+    }
+}
+
+static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+
+fn config_path() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "simple_proxy_reload_{}_{}.json",
+        std::process::id(),
+        CONFIGS.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Routes everything to `upstream`
+fn write_rules(path: &PathBuf, upstream: SocketAddr) {
+    let config = format!(
+        r#"{{
+            "rules": [{{
+                "from": {{ "host": ".*", "path": "(.*)" }},
+                "to": {{ "host": "{}", "path": "$1" }},
+                "public": true
+            }}]
+        }}"#,
+        upstream
+    );
+    std::fs::write(path, config).unwrap();
+}
+
+async fn answer(router: &Router) -> String {
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(router.clone())];
+    let mut service = common::service(middlewares);
+
+    let req = Request::get("/").header("host", "proxy");
+    common::send(&mut service, req.body(Body::empty()).unwrap())
+        .await
+        .1
+}
+
+struct Setup {
+    path: PathBuf,
+    router: Router,
+    b: SocketAddr,
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A router sending to upstream `a`, and the address of upstream `b`
+async fn setup() -> Setup {
+    let a = spawn_upstream("a");
+    let b = spawn_upstream("b");
+    let path = config_path();
+    write_rules(&path, a);
+    let router = Router::new(&Config(path.to_str().unwrap().to_string()));
+    Setup { path, router, b }
+}
+
+#[tokio::test]
+async fn reload_swaps_the_rules() {
+    let setup = setup().await;
+    assert_eq!(answer(&setup.router).await, "a");
+
+    write_rules(&setup.path, setup.b);
+    assert!(setup.router.reload().is_ok());
+    assert_eq!(answer(&setup.router).await, "b");
+}
+
+#[tokio::test]
+async fn invalid_rules_keep_the_current_ones() {
+    let setup = setup().await;
+
+    std::fs::write(&setup.path, r#"{ "rules": [{ "from": "#).unwrap();
+    let err = setup.router.reload().unwrap_err();
+    assert!(err.to_string().contains("cannot parse"), "{}", err);
+    assert_eq!(answer(&setup.router).await, "a");
+
+    std::fs::write(
+        &setup.path,
+        r#"{ "rules": [{ "from": { "host": ".*", "path": ".*" }, "to": { "host": "x", "path": "/" }, "public": true, "upstream": "missing" }] }"#,
+    )
+    .unwrap();
+    assert!(setup.router.reload().is_err());
+    assert_eq!(answer(&setup.router).await, "a");
+}
+
+#[tokio::test]
+async fn watch_reloads_modified_rules() {
+    let setup = setup().await;
+    setup.router.watch(Duration::from_millis(20));
+
+    // Modification times can be as coarse as the filesystem timestamps
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    write_rules(&setup.path, setup.b);
+    let file = std::fs::File::options()
+        .write(true)
+        .open(&setup.path)
+        .unwrap();
+    file.set_modified(std::time::SystemTime::now() + Duration::from_secs(5))
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(answer(&setup.router).await, "b");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sighup_reloads_the_rules() {
+    let setup = setup().await;
+    setup.router.reload_on_sighup().unwrap();
+
+    write_rules(&setup.path, setup.b);
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(answer(&setup.router).await, "b");
+}
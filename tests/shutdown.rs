@@ -0,0 +1,82 @@
+mod common;
+
+use common::Forward;
+use futures::FutureExt;
+use hyper::{Body, Client, Response, StatusCode, Uri};
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Answers after `delay`
+fn spawn_slow_upstream(delay: Duration) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move {
+        tokio::time::sleep(delay).await;
+        Response::new(Body::from("done"))
+    })
+}
+
+/// Starts a proxy to `upstream`, stopped when the returned sender is used
+async fn spawn_proxy(
+    upstream: SocketAddr,
+    timeout: Duration,
+) -> (
+    u16,
+    oneshot::Sender<()>,
+    tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.add_middleware(Box::new(Forward(upstream)));
+    proxy.set_shutdown_timeout(timeout);
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move { proxy.run_until(stopped.map(|_| ())).await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    (port, stop, handle)
+}
+
+#[tokio::test]
+async fn lets_in_flight_requests_finish() {
+    let upstream = spawn_slow_upstream(Duration::from_millis(300));
+    let (port, stop, handle) = spawn_proxy(upstream, Duration::from_secs(5)).await;
+    let uri: Uri = format!("http://127.0.0.1:{}/", port).parse().unwrap();
+
+    let in_flight = tokio::spawn(Client::new().get(uri.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.send(()).unwrap();
+
+    let res = in_flight.await.unwrap().unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        hyper::body::to_bytes(res.into_body()).await.unwrap(),
+        "done"
+    );
+
+    handle.await.unwrap().unwrap();
+
+    // No longer accepting connections
+    assert!(Client::new().get(uri).await.is_err());
+}
+
+#[tokio::test]
+async fn drops_connections_after_the_shutdown_timeout() {
+    let upstream = spawn_slow_upstream(Duration::from_secs(10));
+    let (port, stop, handle) = spawn_proxy(upstream, Duration::from_millis(100)).await;
+    let uri: Uri = format!("http://127.0.0.1:{}/", port).parse().unwrap();
+
+    let in_flight = tokio::spawn(Client::new().get(uri));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let start = Instant::now();
+    stop.send(()).unwrap();
+    handle.await.unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    assert!(in_flight.await.unwrap().is_err());
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use futures::future::{self, poll_fn};
+use hyper::service::Service;
+use hyper::{Body, Request, Response, Uri};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Answers `/slow` after a delay, everything else right away
+fn spawn_upstream() -> SocketAddr {
+    common::spawn_upstream(|req| async move {
+        if req.uri().path() == "/slow" {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        Response::new(Body::from(req.uri().path().to_string()))
+    })
+}
+
+struct Path(String);
+
+/// Keeps track of how many request states are still alive
+struct Alive(#[allow(dead_code)] Arc<()>);
+
+/// Sends every request to the upstream and stores its path in the request state,
+/// echoed back in the `x-state-path` response header.
+struct Tagger {
+    upstream: SocketAddr,
+    alive: Arc<()>,
+}
+
+impl Middleware for Tagger {
+    fn name() -> String {
+        String::from("Tagger")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        state.insert(Path(req.uri().path().to_string()));
+        state.insert(Alive(Arc::clone(&self.alive)));
+        *req.uri_mut() = format!("http://{}{}", self.upstream, req.uri().path()).parse::<Uri>()?;
+        Ok(MiddlewareResult::Next)
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if let (Some(res), Some(Path(path))) = (res, state.get::<Path>()) {
+            res.headers_mut().insert("x-state-path", path.parse()?);
+        }
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+#[tokio::test]
+async fn overlapping_requests_on_one_connection_keep_their_own_state() {
+    let upstream = spawn_upstream();
+    let alive = Arc::new(());
+    let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> = vec![Box::new(Tagger {
+        upstream,
+        alive: Arc::clone(&alive),
+    })];
+
+    // A single service is created per connection
+    let mut service = common::service(middlewares);
+    let idle_count = Arc::strong_count(&alive);
+
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let slow = service.call(Request::get("/slow").body(Body::empty()).unwrap());
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let fast = service.call(Request::get("/fast").body(Body::empty()).unwrap());
+
+    let (slow, fast) = future::join(slow, fast).await;
+    let (slow, fast) = (slow.unwrap(), fast.unwrap());
+
+    assert_eq!(slow.headers()["x-state-path"], "/slow");
+    assert_eq!(fast.headers()["x-state-path"], "/fast");
+
+    // Both request states are dropped with their response
+    assert_eq!(Arc::strong_count(&alive), idle_count);
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use common::Forward;
+use hyper::{Body, Request, Response, StatusCode};
+use simple_proxy::proxy::client::{Clients, PoolConfig, UpstreamTimeouts};
+use simple_proxy::proxy::error::{MiddlewareError, Timeout, UpstreamError};
+use simple_proxy::proxy::middleware::{AsyncMiddleware, Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ProxyService, ServiceContext, State};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream};
+
+/// Answers after `delay`, then streams a second body chunk after `delay` again
+fn spawn_upstream(delay: Duration) -> SocketAddr {
+    common::spawn_upstream(move |_req| async move {
+        tokio::time::sleep(delay).await;
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            sender.send_data("first".into()).await.unwrap();
+            tokio::time::sleep(delay).await;
+            let _ = sender.send_data("second".into()).await;
+        });
+        Response::new(body)
+    })
+}
+
+/// Gives requests the route `timeouts`, records request failures
+struct Route {
+    timeouts: Option<UpstreamTimeouts>,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl Middleware for Route {
+    fn name() -> String {
+        String::from("Route")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if let Some(timeouts) = self.timeouts {
+            state.insert(timeouts);
+        }
+        Ok(MiddlewareResult::Next)
+    }
+
+    fn request_failure(
+        &self,
+        err: &UpstreamError,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        let failure = match err {
+            UpstreamError::Timeout(timeout) => format!("{:?}", timeout),
+            UpstreamError::Http(err) => err.to_string(),
+        };
+        *self.failure.lock().unwrap() = Some(failure);
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+struct Proxy {
+    service: ProxyService,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+fn proxy(
+    upstream: SocketAddr,
+    defaults: UpstreamTimeouts,
+    route: Option<UpstreamTimeouts>,
+) -> Proxy {
+    let failure = Arc::new(Mutex::new(None));
+    let route = Route {
+        timeouts: route,
+        failure: Arc::clone(&failure),
+    };
+    let mut service = common::service(vec![Box::new(Forward(upstream)), Box::new(route)]);
+    service.set_timeouts(defaults);
+    Proxy { service, failure }
+}
+
+async fn send(service: &mut ProxyService) -> Response<Body> {
+    common::call(service, Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn response_header_timeout_is_a_gateway_timeout() {
+    let upstream = spawn_upstream(Duration::from_millis(500));
+    let timeouts = UpstreamTimeouts {
+        response_header_ms: Some(100),
+        ..UpstreamTimeouts::default()
+    };
+    let mut proxy = proxy(upstream, timeouts, None);
+
+    let res = send(&mut proxy.service).await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        proxy.failure.lock().unwrap().as_deref(),
+        Some(format!("{:?}", Timeout::ResponseHeader).as_str())
+    );
+}
+
+#[tokio::test]
+async fn route_timeouts_override_proxy_ones() {
+    let upstream = spawn_upstream(Duration::from_millis(200));
+    let defaults = UpstreamTimeouts {
+        response_header_ms: Some(50),
+        ..UpstreamTimeouts::default()
+    };
+    let route = UpstreamTimeouts {
+        response_header_ms: Some(2000),
+        ..UpstreamTimeouts::default()
+    };
+    let mut proxy = proxy(upstream, defaults, Some(route));
+
+    assert_eq!(send(&mut proxy.service).await.status(), StatusCode::OK);
+    assert!(proxy.failure.lock().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn total_timeout_cuts_the_response_body() {
+    let upstream = spawn_upstream(Duration::from_millis(100));
+    let timeouts = UpstreamTimeouts {
+        total_ms: Some(300),
+        ..UpstreamTimeouts::default()
+    };
+    let mut long = proxy(upstream, timeouts, None);
+
+    let res = send(&mut long.service).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(hyper::body::to_bytes(res.into_body()).await.is_ok());
+
+    let timeouts = UpstreamTimeouts {
+        total_ms: Some(150),
+        ..UpstreamTimeouts::default()
+    };
+    let mut short = proxy(upstream, timeouts, None);
+
+    let res = send(&mut short.service).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(hyper::body::to_bytes(res.into_body()).await.is_err());
+}
+
+#[tokio::test]
+async fn connect_timeout_is_a_gateway_timeout() {
+    // Nothing accepts the connections, once the backlog is full new connections hang
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(([127, 0, 0, 1], 0).into()).unwrap();
+    let listener = socket.listen(1).unwrap();
+    let upstream = listener.local_addr().unwrap();
+    let mut backlog = vec![];
+    while let Ok(Ok(stream)) =
+        tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(upstream)).await
+    {
+        backlog.push(stream);
+    }
+
+    let timeouts = UpstreamTimeouts {
+        connect_ms: Some(100),
+        ..UpstreamTimeouts::default()
+    };
+    let mut proxy = proxy(upstream, timeouts, None);
+
+    let res = send(&mut proxy.service).await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        proxy.failure.lock().unwrap().as_deref(),
+        Some(format!("{:?}", Timeout::Connect).as_str())
+    );
+}
+
+#[tokio::test]
+async fn only_routes_overriding_the_connect_timeout_get_clients_of_their_own() {
+    let upstream = spawn_upstream(Duration::from_millis(0));
+    let defaults = UpstreamTimeouts {
+        connect_ms: Some(1000),
+        ..UpstreamTimeouts::default()
+    };
+    let clients = Arc::new(Clients::with_timeouts(PoolConfig::default(), &defaults));
+    let service = |route: Option<UpstreamTimeouts>| {
+        let route = Route {
+            timeouts: route,
+            failure: Arc::new(Mutex::new(None)),
+        };
+        let middlewares: Vec<Box<dyn AsyncMiddleware + Send + Sync>> =
+            vec![Box::new(Forward(upstream)), Box::new(route)];
+        let mut service = ProxyService::with_clients(
+            Arc::new(middlewares),
+            ([127, 0, 0, 1], 4242).into(),
+            Arc::clone(&clients),
+        );
+        service.set_timeouts(defaults);
+        service
+    };
+
+    let res = send(&mut service(None)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(clients.cached_clients(), 0);
+
+    let route = UpstreamTimeouts {
+        connect_ms: Some(500),
+        ..UpstreamTimeouts::default()
+    };
+    let res = send(&mut service(Some(route))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(clients.cached_clients(), 1);
+}
@@ -0,0 +1,140 @@
+#![cfg(feature = "tls")]
+
+use hyper::{Body, Request, Response};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use simple_proxy::tls::TlsConfig;
+use simple_proxy::{Environment, SimpleProxy};
+use std::convert::TryFrom;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+struct Hello;
+
+impl Middleware for Hello {
+    fn name() -> String {
+        String::from("Hello")
+    }
+
+    fn before_request(
+        &self,
+        _req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        Ok(MiddlewareResult::RespondWith(Response::new(Body::from(
+            "hello",
+        ))))
+    }
+}
+
+/// Writes a self-signed certificate for `hostname`, returns the PEM paths and the DER certificate
+fn self_signed(dir: &Path, hostname: &str) -> (String, String, Certificate) {
+    let cert = rcgen::generate_simple_self_signed(vec![hostname.to_string()]).unwrap();
+    let cert_path = dir.join(format!("{}.crt", hostname));
+    let key_path = dir.join(format!("{}.key", hostname));
+    let pem = cert.serialize_pem().unwrap();
+    std::fs::write(&cert_path, &pem).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+    // Every serialization is signed again, read back the certificate actually written
+    let der = rustls_pemfile::certs(&mut pem.as_bytes())
+        .unwrap()
+        .remove(0);
+    (
+        cert_path.to_str().unwrap().to_string(),
+        key_path.to_str().unwrap().to_string(),
+        Certificate(der),
+    )
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Connects with the given SNI hostname, returns the certificate served and the response body
+async fn get(port: u16, hostname: &str, roots: &RootCertStore) -> (Certificate, String) {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots.clone())
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let mut tcp = None;
+    for _ in 0..50 {
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(stream) => {
+                tcp = Some(stream);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    }
+    let server_name = ServerName::try_from(hostname).unwrap();
+    let stream = connector
+        .connect(server_name, tcp.expect("proxy is not listening"))
+        .await
+        .unwrap();
+    let served = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+    tokio::spawn(conn);
+    let res = sender
+        .send_request(Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+    (served, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn serves_https_with_sni_certificates() {
+    let dir = std::env::temp_dir().join(format!("simple_proxy_tls_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (default_cert, default_key, default_der) = self_signed(&dir, "localhost");
+    let (other_cert, other_key, other_der) = self_signed(&dir, "other.test");
+
+    let mut tls = TlsConfig::from_pem_files(&default_cert, &default_key).unwrap();
+    tls.add_certificate("other.test", &other_cert, &other_key)
+        .unwrap();
+
+    let port = free_port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.add_middleware(Box::new(Hello));
+    proxy.set_tls(tls);
+    tokio::spawn(async move { proxy.run().await });
+
+    let mut roots = RootCertStore::empty();
+    roots.add(&default_der).unwrap();
+    roots.add(&other_der).unwrap();
+
+    let (served, body) = get(port, "localhost", &roots).await;
+    assert_eq!(served, default_der);
+    assert_eq!(body, "hello");
+
+    let (served, body) = get(port, "other.test", &roots).await;
+    assert_eq!(served, other_der);
+    assert_eq!(body, "hello");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_missing_files() {
+    let err = TlsConfig::from_pem_files("/nonexistent/cert.pem", "/nonexistent/key.pem")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("/nonexistent/cert.pem"));
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use simple_proxy::proxy::error::MiddlewareError;
+use simple_proxy::proxy::middleware::{Middleware, MiddlewareResult};
+use simple_proxy::proxy::service::{ServiceContext, State};
+use simple_proxy::{Environment, SimpleProxy};
+use std::net::{SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+/// Switches to an `echo` protocol sending back every byte received
+fn spawn_echo_upstream() -> SocketAddr {
+    common::spawn_upstream(|mut req| async move {
+        tokio::spawn(async move {
+            let mut upgraded = hyper::upgrade::on(&mut req).await.unwrap();
+            let mut buf = [0; 64];
+            while let Ok(n) = upgraded.read(&mut buf).await {
+                if n == 0 || upgraded.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "echo")
+            .body(Body::empty())
+            .unwrap()
+    })
+}
+
+/// Routes to the upstream and marks responses in `after_request`
+struct ToUpstream(SocketAddr);
+
+impl Middleware for ToUpstream {
+    fn name() -> String {
+        String::from("ToUpstream")
+    }
+
+    fn before_request(
+        &self,
+        req: &mut Request<Body>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        *req.uri_mut() = format!("http://{}{}", self.0, req.uri().path()).parse::<Uri>()?;
+        Ok(MiddlewareResult::Next)
+    }
+
+    fn after_request(
+        &self,
+        res: Option<&mut Response<Body>>,
+        _ctx: &ServiceContext,
+        _state: &mut State,
+    ) -> Result<MiddlewareResult, MiddlewareError> {
+        if let Some(res) = res {
+            res.headers_mut().insert("x-after-request", "1".parse()?);
+        }
+        Ok(MiddlewareResult::Next)
+    }
+}
+
+/// Opens an upgraded `echo` connection through the proxy on `port`
+async fn upgrade(port: u16) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: proxy\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    (stream, String::from_utf8(head).unwrap().to_lowercase())
+}
+
+async fn echo(stream: &mut TcpStream, message: &str) {
+    stream.write_all(message.as_bytes()).await.unwrap();
+    let mut buf = vec![0; message.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, message.as_bytes());
+}
+
+#[tokio::test]
+async fn splices_upgraded_connections() {
+    let upstream = spawn_echo_upstream();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.add_middleware(Box::new(ToUpstream(upstream)));
+    tokio::spawn(async move { proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut stream, head) = upgrade(port).await;
+    assert!(head.starts_with("http/1.1 101"), "{}", head);
+    assert!(head.contains("upgrade: echo"), "{}", head);
+    assert!(head.contains("x-after-request: 1"), "{}", head);
+
+    for message in &["ping", "pong"] {
+        echo(&mut stream, message).await;
+    }
+}
+
+#[tokio::test]
+async fn upgraded_connections_are_dropped_after_the_shutdown_timeout() {
+    let upstream = spawn_echo_upstream();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = SimpleProxy::new(port, Environment::Development);
+    proxy.add_middleware(Box::new(ToUpstream(upstream)));
+    proxy.set_shutdown_timeout(Duration::from_millis(300));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let running = tokio::spawn(async move {
+        proxy
+            .run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (mut stream, head) = upgrade(port).await;
+    assert!(head.starts_with("http/1.1 101"), "{}", head);
+    echo(&mut stream, "before").await;
+
+    let start = Instant::now();
+    stop.send(()).unwrap();
+    // Still bridged while draining
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    echo(&mut stream, "during").await;
+
+    running.await.unwrap().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    // Closed once the proxy stopped
+    let mut buf = [0; 8];
+    let _ = stream.write_all(b"after").await;
+    let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "{:?}", read);
+}